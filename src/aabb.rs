@@ -0,0 +1,112 @@
+use std::f64;
+
+use vec3d::Vec3d;
+
+/// Axis aligned bounding box
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vec3d,
+    pub max: Vec3d,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3d, max: Vec3d) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    /// Box that contains nothing, identity for `union`
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3d::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vec3d::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    /// Smallest box that contains all the points
+    pub fn from_points<I>(points: I) -> Aabb
+    where
+        I: IntoIterator<Item = Vec3d>,
+    {
+        points.into_iter().fold(Aabb::empty(), |b, p| b.grow(p))
+    }
+
+    pub fn grow(self, p: Vec3d) -> Aabb {
+        Aabb {
+            min: Vec3d::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+            max: Vec3d::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        self.grow(other.min).grow(other.max)
+    }
+
+    /// Overlap of the two boxes, empty if they don't overlap
+    pub fn intersection(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: Vec3d::new(
+                self.min.x.max(other.min.x),
+                self.min.y.max(other.min.y),
+                self.min.z.max(other.min.z),
+            ),
+            max: Vec3d::new(
+                self.max.x.min(other.max.x),
+                self.max.y.min(other.max.y),
+                self.max.z.min(other.max.z),
+            ),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3d {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let e = self.max - self.min;
+        if e.x < 0.0 || e.y < 0.0 || e.z < 0.0 {
+            0.0
+        } else {
+            2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+        }
+    }
+
+    /// Entry and exit distances of the whole line through the box, negative
+    /// distances included
+    pub fn line_range(&self, p0: Vec3d, inv_d: Vec3d) -> Option<(f64, f64)> {
+        let mut t0 = f64::NEG_INFINITY;
+        let mut t1 = f64::INFINITY;
+
+        for axis in 0..3 {
+            let near = (self.min[axis] - p0[axis]) * inv_d[axis];
+            let far = (self.max[axis] - p0[axis]) * inv_d[axis];
+            let (near, far) = if near > far { (far, near) } else { (near, far) };
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+        }
+
+        if t0 <= t1 {
+            Some((t0, t1))
+        } else {
+            None
+        }
+    }
+
+    /// Slab test, returns the entry distance if the ray hits the box before `t_max`
+    pub fn hit(&self, p0: Vec3d, inv_d: Vec3d, t_max: f64) -> Option<f64> {
+        let mut t0 = 0.0f64;
+        let mut t1 = t_max;
+
+        for axis in 0..3 {
+            let near = (self.min[axis] - p0[axis]) * inv_d[axis];
+            let far = (self.max[axis] - p0[axis]) * inv_d[axis];
+            let (near, far) = if near > far { (far, near) } else { (near, far) };
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t0 > t1 {
+                return None;
+            }
+        }
+
+        Some(t0)
+    }
+}
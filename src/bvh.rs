@@ -0,0 +1,372 @@
+use std::f64;
+
+use aabb::Aabb;
+use vec3d::Vec3d;
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// Cost of visiting a node relative to one primitive test
+const TRAVERSAL_COST: f64 = 0.125;
+
+enum Node {
+    Leaf { bbox: Aabb, start: usize, end: usize },
+    Interior { bbox: Aabb, left: usize, right: usize },
+}
+
+impl Node {
+    fn bbox(&self) -> &Aabb {
+        match *self {
+            Node::Leaf { ref bbox, .. } | Node::Interior { ref bbox, .. } => bbox,
+        }
+    }
+}
+
+// Primitive reference used during construction
+struct Item {
+    index: usize,
+    bbox: Aabb,
+    centroid: Vec3d,
+}
+
+/// Bounding volume hierarchy over primitives identified by index,
+/// built with a binned surface area heuristic
+#[derive(Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds a tree over `(index, bounding box)` pairs
+    pub fn build<I>(prims: I) -> Bvh
+    where
+        I: IntoIterator<Item = (usize, Aabb)>,
+    {
+        let mut items = prims
+            .into_iter()
+            .map(|(index, bbox)| {
+                Item {
+                    index: index,
+                    bbox: bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut bvh = Bvh::default();
+        if !items.is_empty() {
+            bvh.build_node(&mut items, 0);
+        }
+        bvh.indices = items.iter().map(|i| i.index).collect();
+        bvh
+    }
+
+    pub fn bbox(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| *n.bbox())
+    }
+
+    // Builds the subtree for `items`, which start at `offset` in the final
+    // index list, and returns its node index
+    fn build_node(&mut self, items: &mut [Item], offset: usize) -> usize {
+        let bbox = items.iter().fold(Aabb::empty(), |b, i| b.union(i.bbox));
+        let node_idx = self.nodes.len();
+        self.nodes.push(Node::Leaf {
+            bbox: bbox,
+            start: offset,
+            end: offset + items.len(),
+        });
+
+        if items.len() == 1 {
+            return node_idx;
+        }
+
+        let split = match find_split(items, &bbox) {
+            Some(s) => s,
+            None => return node_idx,
+        };
+
+        let mid = partition(items, |i| i.centroid[split.axis] < split.pos);
+        if mid == 0 || mid == items.len() {
+            return node_idx;
+        }
+
+        let (l_items, r_items) = items.split_at_mut(mid);
+        let left = self.build_node(l_items, offset);
+        let right = self.build_node(r_items, offset + mid);
+        self.nodes[node_idx] = Node::Interior {
+            bbox: bbox,
+            left: left,
+            right: right,
+        };
+        node_idx
+    }
+
+    /// Finds the closest primitive hit by the ray, `dist` returns the hit
+    /// distance of a single primitive
+    pub fn closest<F>(&self, p0: Vec3d, d: Vec3d, dist: F) -> Option<(usize, f64)>
+    where
+        F: Fn(usize) -> Option<f64>,
+    {
+        let mut best: Option<(usize, f64)> = None;
+        self.traverse(p0, d, f64::INFINITY, |idx| {
+            if let Some(t) = dist(idx) {
+                if best.map_or(true, |(_, best_t)| t < best_t) {
+                    best = Some((idx, t));
+                }
+            }
+            best.map(|(_, t)| t)
+        });
+        best
+    }
+
+    /// Checks whether any primitive is hit closer than `t_max`
+    pub fn any<F>(&self, p0: Vec3d, d: Vec3d, t_max: f64, dist: F) -> bool
+    where
+        F: Fn(usize) -> Option<f64>,
+    {
+        let mut found = false;
+        self.traverse(p0, d, t_max, |idx| {
+            if dist(idx).map_or(false, |t| t < t_max) {
+                found = true;
+                // Nothing is closer than a negative limit, stops the traversal
+                Some(-1.0)
+            } else {
+                None
+            }
+        });
+        found
+    }
+
+    /// Calls `f` with every primitive whose bounds the ray enters before
+    /// `t_max`, nearer ones first, until it returns false
+    pub fn visit<F>(&self, p0: Vec3d, d: Vec3d, t_max: f64, mut f: F)
+    where
+        F: FnMut(usize) -> bool,
+    {
+        self.traverse(p0, d, t_max, |idx| if f(idx) { None } else { Some(-1.0) });
+    }
+
+    // Visits the leaves front to back. `visit` gets a primitive index and
+    // may return a new, shorter search limit
+    fn traverse<F>(&self, p0: Vec3d, d: Vec3d, t_max: f64, mut visit: F)
+    where
+        F: FnMut(usize) -> Option<f64>,
+    {
+        let inv_d = Vec3d::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let mut limit = t_max;
+        let mut stack = match self.nodes.first().and_then(|n| n.bbox().hit(p0, inv_d, limit)) {
+            Some(t) => vec![(0, t)],
+            None => return,
+        };
+
+        while let Some((node_idx, entry)) = stack.pop() {
+            if entry > limit {
+                continue;
+            }
+
+            match self.nodes[node_idx] {
+                Node::Leaf { start, end, .. } => {
+                    for &idx in &self.indices[start..end] {
+                        if let Some(t) = visit(idx) {
+                            limit = limit.min(t);
+                        }
+                    }
+                    if limit < 0.0 {
+                        return;
+                    }
+                }
+                Node::Interior { left, right, .. } => {
+                    let l_hit = self.nodes[left].bbox().hit(p0, inv_d, limit);
+                    let r_hit = self.nodes[right].bbox().hit(p0, inv_d, limit);
+                    match (l_hit, r_hit) {
+                        (Some(lt), Some(rt)) => {
+                            // Push the farther child first so the nearer is visited first
+                            if lt < rt {
+                                stack.push((right, rt));
+                                stack.push((left, lt));
+                            } else {
+                                stack.push((left, lt));
+                                stack.push((right, rt));
+                            }
+                        }
+                        (Some(lt), None) => stack.push((left, lt)),
+                        (None, Some(rt)) => stack.push((right, rt)),
+                        (None, None) => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Split {
+    axis: usize,
+    pos: f64,
+}
+
+// Evaluates binned SAH splits on all axes, None if a leaf is cheaper
+fn find_split(items: &[Item], bbox: &Aabb) -> Option<Split> {
+    let c_bounds = Aabb::from_points(items.iter().map(|i| i.centroid));
+    let parent_area = bbox.surface_area();
+    let leaf_cost = items.len() as f64;
+
+    let mut best: Option<(f64, Split)> = None;
+
+    for axis in 0..3 {
+        let lo = c_bounds.min[axis];
+        let extent = c_bounds.max[axis] - lo;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bins = [(Aabb::empty(), 0usize); BINS];
+        for i in items {
+            let b = (((i.centroid[axis] - lo) / extent) * BINS as f64) as usize;
+            let b = b.min(BINS - 1);
+            bins[b].0 = bins[b].0.union(i.bbox);
+            bins[b].1 += 1;
+        }
+
+        // Sweep from the right to get the cost of every right side
+        let mut right_cost = [0.0; BINS];
+        let mut acc = (Aabb::empty(), 0);
+        for b in (1..BINS).rev() {
+            acc = (acc.0.union(bins[b].0), acc.1 + bins[b].1);
+            right_cost[b] = acc.0.surface_area() * acc.1 as f64;
+        }
+
+        let mut acc = (Aabb::empty(), 0);
+        for b in 0..BINS - 1 {
+            acc = (acc.0.union(bins[b].0), acc.1 + bins[b].1);
+            let left_cost = acc.0.surface_area() * acc.1 as f64;
+            let cost = if parent_area > 0.0 {
+                TRAVERSAL_COST + (left_cost + right_cost[b + 1]) / parent_area
+            } else {
+                TRAVERSAL_COST + leaf_cost / 2.0
+            };
+
+            if best.as_ref().map_or(true, |&(c, _)| cost < c) {
+                let pos = lo + extent * (b + 1) as f64 / BINS as f64;
+                best = Some((cost, Split { axis: axis, pos: pos }));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, split)) if cost < leaf_cost || items.len() > MAX_LEAF_SIZE => Some(split),
+        _ => None,
+    }
+}
+
+// Moves the items matching `pred` to the front, returns their count
+fn partition<F>(items: &mut [Item], pred: F) -> usize
+where
+    F: Fn(&Item) -> bool,
+{
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use cgmath::*;
+    use super::*;
+
+    // Linear congruential generator, enough to scatter test boxes
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, min: f64, max: f64) -> f64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            min + (max - min) * (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn vec(&mut self, min: f64, max: f64) -> Vec3d {
+            Vec3d::new(self.next(min, max), self.next(min, max), self.next(min, max))
+        }
+    }
+
+    fn random_boxes(rng: &mut Lcg, n: usize) -> Vec<Aabb> {
+        (0..n)
+            .map(|_| {
+                let center = rng.vec(-10.0, 10.0);
+                let half = rng.vec(0.05, 1.0);
+                Aabb::new(center - half, center + half)
+            })
+            .collect()
+    }
+
+    fn dist(boxes: &[Aabb], p0: Vec3d, d: Vec3d, i: usize) -> Option<f64> {
+        let inv_d = Vec3d::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        boxes[i].hit(p0, inv_d, f64::INFINITY)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut rng = Lcg(7);
+        let boxes = random_boxes(&mut rng, 200);
+        let bvh = Bvh::build(boxes.iter().cloned().enumerate());
+
+        for _ in 0..2000 {
+            let p0 = rng.vec(-12.0, 12.0);
+            let d = rng.vec(-1.0, 1.0).normalize();
+            let t_max = rng.next(0.0, 20.0);
+            let hit = |i| dist(&boxes, p0, d, i);
+
+            let expected = (0..boxes.len())
+                .filter_map(hit)
+                .fold(None, |min: Option<f64>, t| Some(min.map_or(t, |m| m.min(t))));
+            match (bvh.closest(p0, d, hit), expected) {
+                (Some((i, t)), Some(e)) => {
+                    assert_eq!(t, e);
+                    assert_eq!(hit(i), Some(t));
+                }
+                (None, None) => {}
+                (found, e) => panic!("closest {:?}, expected {:?}", found, e),
+            }
+
+            let any = (0..boxes.len()).any(|i| hit(i).is_some_and(|t| t < t_max));
+            assert_eq!(bvh.any(p0, d, t_max, hit), any);
+        }
+    }
+
+    #[test]
+    fn any_stops_at_the_first_hit() {
+        // A row of boxes along the ray, the first leaf is enough
+        let boxes = (0..64)
+            .map(|i| {
+                let x = i as f64 * 2.0;
+                Aabb::new(Vec3d::new(x, -0.5, -0.5), Vec3d::new(x + 1.0, 0.5, 0.5))
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(boxes.iter().cloned().enumerate());
+        let (p0, d) = (Vec3d::new(-1.0, 0.0, 0.0), Vec3d::new(1.0, 0.0, 0.0));
+
+        let calls = Cell::new(0);
+        let hit = |i| {
+            calls.set(calls.get() + 1);
+            dist(&boxes, p0, d, i)
+        };
+        assert!(bvh.any(p0, d, f64::INFINITY, hit));
+        assert!(calls.get() <= MAX_LEAF_SIZE, "{} primitives tested", calls.get());
+    }
+
+    #[test]
+    fn empty() {
+        let bvh = Bvh::build(Vec::new());
+        let (p0, d) = (Vec3d::zero(), Vec3d::new(0.0, 0.0, 1.0));
+        let hit = |_| -> Option<f64> { panic!("no primitives to test") };
+
+        assert!(bvh.bbox().is_none());
+        assert_eq!(bvh.closest(p0, d, hit), None);
+        assert!(!bvh.any(p0, d, f64::INFINITY, hit));
+    }
+}
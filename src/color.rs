@@ -1,6 +1,7 @@
 use std::ops::{Add, Mul};
 use std::iter::Sum;
 
+/// Linear RGB color, all shading happens in this space
 #[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct Color(pub f64, pub f64, pub f64);
 
@@ -10,6 +11,46 @@ pub const BLUE: Color = Color(0.0, 0.0, 1.0);
 pub const WHITE: Color = Color(1.0, 1.0, 1.0);
 pub const BLACK: Color = Color(0.0, 0.0, 0.0);
 
+impl Color {
+    /// Relative luminance with Rec. 709 weights
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
+    /// Decodes a color given in sRGB into linear space
+    pub fn from_srgb(self) -> Color {
+        Color(srgb_decode(self.0), srgb_decode(self.1), srgb_decode(self.2))
+    }
+
+    /// Encodes a linear color with the sRGB transfer function
+    pub fn to_srgb(self) -> Color {
+        Color(srgb_encode(self.0), srgb_encode(self.1), srgb_encode(self.2))
+    }
+
+    /// Limits every channel to [0, 1]
+    pub fn clamp(self) -> Color {
+        Color(self.0.clamp(0.0, 1.0), self.1.clamp(0.0, 1.0), self.2.clamp(0.0, 1.0))
+    }
+}
+
+/// sRGB transfer function, linear to encoded
+pub fn srgb_encode(v: f64) -> f64 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse sRGB transfer function, encoded to linear
+pub fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
 impl Add for Color {
     type Output = Color;
 
@@ -34,22 +75,15 @@ impl Mul<f64> for Color {
     }
 }
 
+/// Clamps and sRGB encodes to 8-bit output
 impl Into<(u8, u8, u8)> for Color {
-    fn into(mut self) -> (u8, u8, u8) {
-        if self.0 > 1.0 {
-            self.0 = 1.0;
-        }
-        if self.1 > 1.0 {
-            self.1 = 1.0;
-        }
-        if self.2 > 1.0 {
-            self.2 = 1.0;
-        }
+    fn into(self) -> (u8, u8, u8) {
+        let c = self.clamp().to_srgb();
 
         (
-            (255.0 * self.0) as u8,
-            (255.0 * self.1) as u8,
-            (255.0 * self.2) as u8,
+            (255.0 * c.0 + 0.5) as u8,
+            (255.0 * c.1 + 0.5) as u8,
+            (255.0 * c.2 + 0.5) as u8,
         )
     }
 }
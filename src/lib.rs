@@ -1,11 +1,22 @@
 extern crate cgmath;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate png;
 
+pub mod aabb;
+pub mod bvh;
 pub mod color;
 pub mod vec3d;
 pub mod light;
 pub mod scene;
 pub mod material;
 pub mod shape;
+pub mod loader;
+pub mod output;
+pub mod poly;
+pub mod sampling;
+pub mod tonemap;
+pub mod transform;
+pub mod subdivision;
@@ -28,6 +28,10 @@ impl Light {
         self.color
     }
 
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
     pub fn feeler(&self, point: Vec3d) -> (Vec3d, f64) {
         let feeler_d = self.pos - point;
         (feeler_d.normalize(), feeler_d.magnitude())
@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use cgmath::*;
+use vec3d::Vec3d;
+use aabb::Aabb;
+use material::Material;
+use shape::Shape;
+use shape::bezier::BezierPatch;
+use scene::CraycrayError;
+
+// Tessellation tolerance relative to the size of the control points
+const RELATIVE_TOLERANCE: f64 = 1e-3;
+
+fn parse_err(path: &Path, line: usize, msg: &str) -> CraycrayError {
+    CraycrayError::Parse {
+        file: path.display().to_string(),
+        line: line,
+        msg: msg.to_string(),
+    }
+}
+
+// Numbers of the file with their line, separated by commas or whitespace
+struct Tokens<'a> {
+    path: &'a Path,
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(path: &'a Path, text: &'a str) -> Tokens<'a> {
+        let tokens = text.lines()
+            .enumerate()
+            .flat_map(|(line_no, line)| {
+                line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|t| !t.is_empty())
+                    .map(move |t| (line_no + 1, t))
+            })
+            .collect();
+        Tokens {
+            path: path,
+            tokens: tokens,
+            pos: 0,
+        }
+    }
+
+    fn next(&mut self) -> Result<(usize, &'a str), CraycrayError> {
+        let last_line = self.tokens.last().map_or(1, |&(line, _)| line);
+        let token = self.tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| parse_err(self.path, last_line, "unexpected end of file"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn count(&mut self) -> Result<(usize, usize), CraycrayError> {
+        let (line, token) = self.next()?;
+        token
+            .parse()
+            .map(|n| (line, n))
+            .map_err(|_| parse_err(self.path, line, "invalid number"))
+    }
+
+    fn float(&mut self) -> Result<f64, CraycrayError> {
+        let (line, token) = self.next()?;
+        token
+            .parse()
+            .map_err(|_| parse_err(self.path, line, "invalid number"))
+    }
+}
+
+/// Reads the patches of a file in the teapot format: the patch count, 16
+/// 1-based control point indices per patch, the point count and the points
+pub fn load_patches(path: &Path) -> Result<Vec<BezierPatch>, CraycrayError> {
+    let f = File::open(path).map_err(CraycrayError::Io)?;
+    let mut text = String::new();
+    BufReader::new(f)
+        .read_to_string(&mut text)
+        .map_err(CraycrayError::Io)?;
+    parse_patches(path, &text)
+}
+
+fn parse_patches(path: &Path, text: &str) -> Result<Vec<BezierPatch>, CraycrayError> {
+    let mut tokens = Tokens::new(path, text);
+
+    let (_, n_patches) = tokens.count()?;
+    let mut patch_indices = Vec::with_capacity(n_patches);
+    for _ in 0..n_patches {
+        let mut indices = [(0, 0); 16];
+        for index in indices.iter_mut() {
+            *index = tokens.count()?;
+        }
+        patch_indices.push(indices);
+    }
+
+    let (_, n_points) = tokens.count()?;
+    let mut points = Vec::with_capacity(n_points);
+    for _ in 0..n_points {
+        let (x, y, z) = (tokens.float()?, tokens.float()?, tokens.float()?);
+        points.push(Vec3d::new(x, y, z));
+    }
+
+    patch_indices
+        .iter()
+        .map(|indices| {
+            let mut control = [Vec3d::zero(); 16];
+            for (dst, &(line, i)) in control.iter_mut().zip(indices.iter()) {
+                if i == 0 || i > points.len() {
+                    return Err(parse_err(path, line, "control point index out of range"));
+                }
+                *dst = points[i - 1];
+            }
+            Ok(BezierPatch::new(control))
+        })
+        .collect()
+}
+
+/// Loads a teapot format patch file as a mesh, the control points are scaled
+/// then moved to `position`
+pub fn load_bezier(
+    path: &Path,
+    position: Vec3d,
+    scale: f64,
+    material: Material,
+) -> Result<Shape, CraycrayError> {
+    let patches: Vec<BezierPatch> = load_patches(path)?
+        .iter()
+        .map(|patch| {
+            let mut points = *patch.points();
+            for p in points.iter_mut() {
+                *p = *p * scale + position;
+            }
+            BezierPatch::new(points)
+        })
+        .collect();
+
+    if patches.is_empty() {
+        return Err(CraycrayError::InvalidData {
+            file: path.display().to_string(),
+            msg: "no patches".to_string(),
+        });
+    }
+
+    let bbox = patches.iter().fold(Aabb::empty(), |bbox, patch| {
+        bbox.union(Aabb::from_points(patch.points().iter().cloned()))
+    });
+    let tolerance = (bbox.max - bbox.min).magnitude() * RELATIVE_TOLERANCE;
+    Ok(Shape::new_bezier_material(&patches, tolerance, material))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One patch using the points `indices` of a file with 16 points
+    fn parse(indices: &str) -> Result<Vec<BezierPatch>, CraycrayError> {
+        let points: Vec<String> = (0..16)
+            .map(|k| format!("{}, {}, {}", k % 4, k / 4, k))
+            .collect();
+        let text = format!("1\n{}\n16\n{}\n", indices, points.join("\n"));
+        parse_patches(Path::new("test.bpt"), &text)
+    }
+
+    fn indices(last: usize) -> String {
+        let mut indices: Vec<String> = (1..16).map(|i| i.to_string()).collect();
+        indices.push(last.to_string());
+        indices.join(",")
+    }
+
+    #[test]
+    fn patches() {
+        let patches = parse(&indices(16)).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].points()[0], Vec3d::new(0.0, 0.0, 0.0));
+        assert_eq!(patches[0].points()[15], Vec3d::new(3.0, 3.0, 15.0));
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        for &last in &[0, 17] {
+            match parse(&indices(last)) {
+                Err(CraycrayError::Parse { line: 2, .. }) => {}
+                _ => panic!("index {} accepted", last),
+            }
+        }
+    }
+}
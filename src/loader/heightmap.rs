@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use png;
+
+use scene::CraycrayError;
+
+/// Grayscale image with its samples scaled to [0, 1], row by row
+pub struct HeightMap {
+    pub width: usize,
+    pub height: usize,
+    pub samples: Vec<f64>,
+}
+
+/// Loads a PNG or a PGM (P2 or P5) image, RGB pixels are averaged
+pub fn load_heightmap(path: &Path) -> Result<HeightMap, CraycrayError> {
+    let is_png = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"));
+
+    let file = File::open(path).map_err(CraycrayError::Io)?;
+    if is_png {
+        load_png(file)
+    } else {
+        let mut data = Vec::new();
+        BufReader::new(file)
+            .read_to_end(&mut data)
+            .map_err(CraycrayError::Io)?;
+        load_pgm(path, &data)
+    }
+}
+
+fn load_png<R: Read>(r: R) -> Result<HeightMap, CraycrayError> {
+    // The default transformations expand everything to 8 bits per sample,
+    // palette images are expanded to RGB
+    let (info, mut reader) = png::Decoder::new(r)
+        .read_info()
+        .map_err(CraycrayError::PngDecode)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buf).map_err(CraycrayError::PngDecode)?;
+
+    let (color_type, _) = reader.output_color_type();
+    // Alpha is ignored, color channels are averaged
+    let (stride, channels) = match color_type {
+        png::ColorType::Grayscale => (1, 1),
+        png::ColorType::GrayscaleAlpha => (2, 1),
+        png::ColorType::RGB | png::ColorType::Indexed => (3, 3),
+        png::ColorType::RGBA => (4, 3),
+    };
+
+    let samples = buf.chunks(stride)
+        .map(|px| {
+            let sum: u32 = px[..channels].iter().map(|&c| u32::from(c)).sum();
+            f64::from(sum) / (255.0 * channels as f64)
+        })
+        .collect();
+
+    Ok(HeightMap {
+        width: info.width as usize,
+        height: info.height as usize,
+        samples: samples,
+    })
+}
+
+// Header tokens of a netpbm file
+struct Header<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Header<'a> {
+    fn err(&self, msg: &str) -> CraycrayError {
+        CraycrayError::Parse {
+            file: self.path.display().to_string(),
+            line: self.line,
+            msg: msg.to_string(),
+        }
+    }
+
+    // Next whitespace separated token, comments run to the end of the line
+    fn token(&mut self) -> Result<&'a str, CraycrayError> {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'#' => while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                    self.pos += 1;
+                },
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                c if (c as char).is_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        let start = self.pos;
+        while self.pos < self.data.len() && !(self.data[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.err("unexpected end of file"));
+        }
+        ::std::str::from_utf8(&self.data[start..self.pos]).map_err(|_| self.err("invalid token"))
+    }
+
+    fn number(&mut self) -> Result<usize, CraycrayError> {
+        let token = self.token()?;
+        token.parse().map_err(|_| self.err(&format!("invalid number '{}'", token)))
+    }
+}
+
+fn load_pgm(path: &Path, data: &[u8]) -> Result<HeightMap, CraycrayError> {
+    let mut header = Header {
+        path: path,
+        data: data,
+        pos: 0,
+        line: 1,
+    };
+
+    let magic = header.token()?;
+    if magic != "P2" && magic != "P5" {
+        return Err(header.err("not a PGM file"));
+    }
+    let width = header.number()?;
+    let height = header.number()?;
+    let max = header.number()?;
+    if max == 0 || max > 65_535 {
+        return Err(header.err("invalid maximum value"));
+    }
+
+    let n = width.checked_mul(height).ok_or_else(|| {
+        CraycrayError::InvalidData {
+            file: path.display().to_string(),
+            msg: format!("image size {}x{} is too large", width, height),
+        }
+    })?;
+    let samples: Vec<usize> = if magic == "P2" {
+        (0..n).map(|_| header.number()).collect::<Result<_, _>>()?
+    } else {
+        // A single whitespace byte separates the header from the pixels
+        let start = header.pos + 1;
+        let bytes = if max < 256 { 1 } else { 2 };
+        let end = n.checked_mul(bytes).and_then(|len| len.checked_add(start));
+        let end = match end {
+            Some(end) if end <= data.len() => end,
+            _ => return Err(header.err("not enough pixel data")),
+        };
+        data[start..end]
+            .chunks(bytes)
+            .map(|b| b.iter().fold(0, |v, &byte| (v << 8) | byte as usize))
+            .collect()
+    };
+
+    Ok(HeightMap {
+        width: width,
+        height: height,
+        samples: samples.into_iter().map(|v| v as f64 / max as f64).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use png::HasParameters;
+    use super::*;
+
+    fn pgm(data: &[u8]) -> Result<HeightMap, CraycrayError> {
+        load_pgm(Path::new("test.pgm"), data)
+    }
+
+    #[test]
+    fn palette_png() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, 2, 2);
+            encoder.set(png::ColorType::Indexed).set(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_chunk(*b"PLTE", &[0, 0, 0, 255, 255, 255, 255, 0, 0]).unwrap();
+            writer.write_image_data(&[0, 1, 2, 1]).unwrap();
+        }
+
+        let map = load_png(&data[..]).unwrap();
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(map.samples, vec![0.0, 1.0, 1.0 / 3.0, 1.0]);
+    }
+
+    #[test]
+    fn ascii_and_binary_pgm() {
+        let ascii = pgm(b"P2\n# comment\n2 1\n4\n0 4\n").unwrap();
+        assert_eq!(ascii.samples, vec![0.0, 1.0]);
+        let binary = pgm(b"P5 2 1 255\n\x00\xff").unwrap();
+        assert_eq!(binary.samples, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn malformed_pgm() {
+        match pgm(b"P5 18446744073709551615 2 255\n") {
+            Err(CraycrayError::InvalidData { .. }) => {}
+            _ => panic!("overflowing size accepted"),
+        }
+        match pgm(b"P5 2 2 255\n\x00\xff") {
+            Err(CraycrayError::Parse { .. }) => {}
+            _ => panic!("truncated pixels accepted"),
+        }
+    }
+}
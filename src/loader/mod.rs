@@ -0,0 +1,66 @@
+pub mod obj;
+pub mod ply;
+pub mod stl;
+pub mod bezier;
+pub mod heightmap;
+
+use std::path::Path;
+
+use cgmath::*;
+use vec3d::Vec3d;
+use material::Material;
+use shape::Shape;
+use scene::CraycrayError;
+
+/// External model referenced from a scene file, an OBJ, PLY or STL file or
+/// Bézier patches in the teapot format (`.bpt`)
+#[derive(Serialize, Deserialize)]
+pub struct Model {
+    path: String,
+    #[serde(default = "default_position")]
+    position: Vec3d,
+    #[serde(default = "default_scale")]
+    scale: f64,
+    #[serde(default)]
+    material: Option<Material>,
+    /// Subdivision levels applied to the loaded meshes, Bézier patches are
+    /// tessellated to the surface and can't be subdivided
+    #[serde(default)]
+    subdivision: usize,
+}
+
+fn default_position() -> Vec3d {
+    Vec3d::zero()
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Model {
+    /// Loads the model, relative paths are resolved against `base_dir`
+    pub fn load(&self, base_dir: &Path) -> Result<Vec<Shape>, CraycrayError> {
+        let path = base_dir.join(&self.path);
+        let material = self.material.clone().unwrap_or_else(obj::default_material);
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        let (position, scale, levels) = (self.position, self.scale, self.subdivision);
+
+        match ext.as_deref() {
+            Some("obj") => obj::load_obj(&path, position, scale, &material, levels),
+            Some("ply") => {
+                ply::load_ply(&path, position, scale, material, levels).map(|s| vec![s])
+            }
+            Some("stl") => {
+                stl::load_stl(&path, position, scale, material, levels).map(|s| vec![s])
+            }
+            Some("bpt") if levels > 0 => Err(CraycrayError::InvalidShape(format!(
+                "{}: Bézier patches can't be subdivided",
+                path.display()
+            ))),
+            Some("bpt") => bezier::load_bezier(&path, position, scale, material).map(|s| vec![s]),
+            _ => Err(CraycrayError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use cgmath::*;
+use vec3d::Vec3d;
+use color;
+use color::Color;
+use material::Material;
+use shape::Shape;
+use shape::mesh::Mesh;
+use subdivision::{fan_triangles, stray_crease, ControlMesh};
+use scene::CraycrayError;
+
+/// Material used for faces that have no `usemtl`
+pub fn default_material() -> Material {
+    Material {
+        ambient_color: color::BLACK,
+        specular_color: color::WHITE,
+        diffuse_color: Color(0.8, 0.8, 0.8),
+        shininess: 15.0,
+        reflectivity: 0.0,
+        transparency: 0.0,
+        ior: 1.0,
+        trap_color: None,
+    }
+}
+
+// Face corner, the vertex and the normal if the face gives one
+type Corner = (usize, Option<usize>);
+
+// Crease edge between two vertices and its sharpness
+type Crease = (usize, usize, f64);
+
+// Faces that share a material, indices point to the global vertex and
+// normal lists
+struct Group {
+    material: Material,
+    faces: Vec<Vec<Corner>>,
+}
+
+impl Group {
+    fn new(material: Material) -> Group {
+        Group {
+            material: material,
+            faces: Vec::new(),
+        }
+    }
+
+    // Compact the vertices used by this group into its own mesh. Corners
+    // with different normals become separate vertices, unless the mesh is
+    // subdivided, which ignores the normals
+    fn into_shape(
+        self,
+        vertices: &[Vec3d],
+        normals: &[Vec3d],
+        creases: &[Crease],
+        levels: usize,
+    ) -> Shape {
+        let mut remap = HashMap::new();
+        let mut mesh_vertices = Vec::new();
+        let mut mesh_normals = Vec::new();
+        let faces: Vec<Vec<usize>> = self.faces
+            .iter()
+            .map(|face| {
+                face.iter()
+                    .map(|&(v, n)| {
+                        let key = if levels > 0 { (v, None) } else { (v, n) };
+                        *remap.entry(key).or_insert_with(|| {
+                            mesh_vertices.push(vertices[v]);
+                            mesh_normals.push(n.map(|n| normals[n]));
+                            mesh_vertices.len() - 1
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        if levels > 0 {
+            let mut control = ControlMesh::new(mesh_vertices, faces);
+            for &(a, b, sharpness) in creases {
+                if let (Some(&a), Some(&b)) = (remap.get(&(a, None)), remap.get(&(b, None))) {
+                    control.add_crease(a, b, sharpness);
+                }
+            }
+            return Shape::Mesh(control.subdivide(levels).into_mesh(self.material));
+        }
+
+        // Normals are generated unless every corner has one
+        let normals = mesh_normals
+            .into_iter()
+            .collect::<Option<Vec<Vec3d>>>()
+            .unwrap_or_default();
+        let indices = fan_triangles(&faces);
+        Shape::Mesh(Mesh::from_normals(mesh_vertices, normals, indices, self.material))
+    }
+}
+
+fn parse_err(path: &Path, line: usize, msg: &str) -> CraycrayError {
+    CraycrayError::Parse {
+        file: path.display().to_string(),
+        line: line,
+        msg: msg.to_string(),
+    }
+}
+
+fn parse_floats<'a, I>(path: &Path, line: usize, args: I, n: usize) -> Result<Vec<f64>, CraycrayError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let vals = args.take(n)
+        .map(|a| a.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| parse_err(path, line, "invalid number"))?;
+
+    if vals.len() == n {
+        Ok(vals)
+    } else {
+        Err(parse_err(path, line, "missing values"))
+    }
+}
+
+fn parse_color<'a, I>(path: &Path, line: usize, args: I) -> Result<Color, CraycrayError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let v = parse_floats(path, line, args, 3)?;
+    Ok(Color(v[0], v[1], v[2]))
+}
+
+// Resolve a 1-based (or negative, relative) OBJ vertex or normal reference
+fn parse_index(path: &Path, line: usize, arg: &str, n: usize) -> Result<usize, CraycrayError> {
+    let idx = arg.parse::<isize>()
+        .map_err(|_| parse_err(path, line, "invalid face index"))?;
+
+    let resolved = if idx < 0 { n as isize + idx } else { idx - 1 };
+
+    if resolved >= 0 && (resolved as usize) < n {
+        Ok(resolved as usize)
+    } else {
+        Err(parse_err(path, line, "face index out of range"))
+    }
+}
+
+// Resolve a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner
+fn parse_corner(
+    path: &Path,
+    line: usize,
+    arg: &str,
+    n_vertices: usize,
+    n_normals: usize,
+) -> Result<Corner, CraycrayError> {
+    let mut refs = arg.split('/');
+    let vertex = parse_index(path, line, refs.next().unwrap_or(""), n_vertices)?;
+    let normal = match refs.nth(1) {
+        Some(n) if !n.is_empty() => Some(parse_index(path, line, n, n_normals)?),
+        _ => None,
+    };
+    Ok((vertex, normal))
+}
+
+// OpenSubdiv's `t crease <ints>/<floats>/0` tag, the ints are pairs of
+// 0-based vertex indices and the floats one sharpness for all edges or one
+// for each
+fn parse_crease<'a, I>(path: &Path, line: usize, mut args: I) -> Result<Vec<Crease>, CraycrayError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let counts = args.next()
+        .map(|c| c.split('/').map(|n| n.parse::<usize>()).collect::<Result<Vec<_>, _>>());
+    let (n_ints, n_floats) = match counts {
+        Some(Ok(ref c)) if c.len() == 3 && c[0] % 2 == 0 => (c[0], c[1]),
+        _ => return Err(parse_err(path, line, "invalid crease tag")),
+    };
+
+    let ints = args.by_ref()
+        .take(n_ints)
+        .map(|a| a.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| parse_err(path, line, "invalid crease vertex"))?;
+    let floats = parse_floats(path, line, args, n_floats)?;
+    if ints.len() != n_ints || (floats.len() != 1 && floats.len() != n_ints / 2) {
+        return Err(parse_err(path, line, "crease needs one sharpness or one per edge"));
+    }
+
+    Ok(ints.chunks(2)
+        .enumerate()
+        .map(|(i, e)| (e[0], e[1], floats[i.min(floats.len() - 1)]))
+        .collect())
+}
+
+/// Reads the materials of an MTL file, `base` supplies the fields MTL has no notion of
+pub fn load_mtl(path: &Path, base: &Material) -> Result<HashMap<String, Material>, CraycrayError> {
+    let f = File::open(path).map_err(CraycrayError::Io)?;
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+
+    for (line_no, line) in BufReader::new(f).lines().enumerate() {
+        let line = line.map_err(CraycrayError::Io)?;
+        let line_no = line_no + 1;
+        let mut args = line.split_whitespace();
+        let keyword = match args.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = args.collect::<Vec<_>>().join(" ");
+            if let Some((name, m)) = current.take() {
+                materials.insert(name, m);
+            }
+            current = Some((name, base.clone()));
+            continue;
+        }
+
+        let m = match current {
+            Some((_, ref mut m)) => m,
+            None => continue,
+        };
+
+        match keyword {
+            "Kd" => m.diffuse_color = parse_color(path, line_no, args)?,
+            "Ks" => m.specular_color = parse_color(path, line_no, args)?,
+            "Ka" => m.ambient_color = parse_color(path, line_no, args)?,
+            "Ns" => m.shininess = parse_floats(path, line_no, args, 1)?[0],
+            "Ni" => m.ior = parse_floats(path, line_no, args, 1)?[0],
+            "d" => m.transparency = 1.0 - parse_floats(path, line_no, args, 1)?[0],
+            "Tr" => m.transparency = parse_floats(path, line_no, args, 1)?[0],
+            _ => {}
+        }
+    }
+
+    if let Some((name, m)) = current.take() {
+        materials.insert(name, m);
+    }
+
+    Ok(materials)
+}
+
+/// Loads an OBJ file as one mesh per material, vertices are scaled then moved to `position`.
+/// The meshes are subdivided `levels` times, keeping the edges of `t crease` tags sharp
+pub fn load_obj(
+    path: &Path,
+    position: Vec3d,
+    scale: f64,
+    default: &Material,
+    levels: usize,
+) -> Result<Vec<Shape>, CraycrayError> {
+    let f = File::open(path).map_err(CraycrayError::Io)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut creases = Vec::new();
+    let mut materials = HashMap::new();
+    let mut groups: Vec<(String, Group)> = Vec::new();
+    let mut current = String::new();
+
+    for (line_no, line) in BufReader::new(f).lines().enumerate() {
+        let line = line.map_err(CraycrayError::Io)?;
+        let line_no = line_no + 1;
+        let mut args = line.split_whitespace();
+        let keyword = match args.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(path, line_no, args, 3)?;
+                vertices.push(Vec3d::new(v[0], v[1], v[2]) * scale + position);
+            }
+            "vn" => {
+                let n = parse_floats(path, line_no, args, 3)?;
+                normals.push(Vec3d::new(n[0], n[1], n[2]).normalize());
+            }
+            "f" => {
+                let face = args.map(|a| {
+                    parse_corner(path, line_no, a, vertices.len(), normals.len())
+                }).collect::<Result<Vec<Corner>, _>>()?;
+                if face.len() < 3 {
+                    return Err(parse_err(path, line_no, "face needs at least 3 vertices"));
+                }
+
+                if groups.last().map_or(true, |&(ref name, _)| *name != current) {
+                    let m = materials.get(&current).cloned().unwrap_or_else(|| default.clone());
+                    groups.push((current.clone(), Group::new(m)));
+                }
+                groups.last_mut().unwrap().1.faces.push(face);
+            }
+            "t" if args.next() == Some("crease") => {
+                creases.extend(parse_crease(path, line_no, args)?);
+            }
+            "mtllib" => {
+                for lib in args {
+                    materials.extend(load_mtl(&dir.join(lib), default)?);
+                }
+            }
+            "usemtl" => {
+                current = args.collect::<Vec<_>>().join(" ");
+            }
+            _ => {}
+        }
+    }
+
+    let faces: Vec<Vec<usize>> = groups
+        .iter()
+        .flat_map(|group| group.1.faces.iter())
+        .map(|face| face.iter().map(|&(v, _)| v).collect())
+        .collect();
+    if let Some((a, b)) = stray_crease(faces.iter().map(|face| &face[..]), &creases) {
+        return Err(CraycrayError::InvalidData {
+            file: path.display().to_string(),
+            msg: format!("crease {}-{} is not an edge of a face", a, b),
+        });
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(_, g)| g.into_shape(&vertices, &normals, &creases, levels))
+        .collect())
+}
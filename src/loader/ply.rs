@@ -0,0 +1,565 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::str;
+
+use vec3d::Vec3d;
+use color::Color;
+use material::Material;
+use shape::Shape;
+use shape::mesh::Mesh;
+use subdivision::{fan_triangles, stray_crease, ControlMesh};
+use scene::CraycrayError;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::Char),
+            "uchar" | "uint8" => Some(Scalar::UChar),
+            "short" | "int16" => Some(Scalar::Short),
+            "ushort" | "uint16" => Some(Scalar::UShort),
+            "int" | "int32" => Some(Scalar::Int),
+            "uint" | "uint32" => Some(Scalar::UInt),
+            "float" | "float32" => Some(Scalar::Float),
+            "double" | "float64" => Some(Scalar::Double),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Scalar::Char | Scalar::UChar => 1,
+            Scalar::Short | Scalar::UShort => 2,
+            Scalar::Int | Scalar::UInt | Scalar::Float => 4,
+            Scalar::Double => 8,
+        }
+    }
+
+    // Value of full intensity for color channels of this type
+    fn color_max(&self) -> f64 {
+        match *self {
+            Scalar::Char => 127.0,
+            Scalar::UChar => 255.0,
+            Scalar::Short => 32_767.0,
+            Scalar::UShort => 65_535.0,
+            Scalar::Int => 2_147_483_647.0,
+            Scalar::UInt => 4_294_967_295.0,
+            Scalar::Float | Scalar::Double => 1.0,
+        }
+    }
+
+    // Decodes a binary value, `bytes` has exactly `size()` bytes in file order
+    fn decode(&self, bytes: &[u8], format: Format) -> f64 {
+        let mut raw = 0u64;
+        for i in 0..bytes.len() {
+            let b = if format == Format::BigEndian {
+                bytes[i]
+            } else {
+                bytes[bytes.len() - 1 - i]
+            };
+            raw = (raw << 8) | u64::from(b);
+        }
+
+        match *self {
+            Scalar::Char => f64::from(raw as u8 as i8),
+            Scalar::UChar => f64::from(raw as u8),
+            Scalar::Short => f64::from(raw as u16 as i16),
+            Scalar::UShort => f64::from(raw as u16),
+            Scalar::Int => f64::from(raw as u32 as i32),
+            Scalar::UInt => f64::from(raw as u32),
+            Scalar::Float => f64::from(f32::from_bits(raw as u32)),
+            Scalar::Double => f64::from_bits(raw),
+        }
+    }
+}
+
+enum Property {
+    Scalar(Scalar),
+    /// Count type and item type
+    List(Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|&(ref name, _)| names.contains(&name.as_str()))
+    }
+}
+
+fn parse_err(path: &Path, line: usize, msg: &str) -> CraycrayError {
+    CraycrayError::Parse {
+        file: path.display().to_string(),
+        line: line,
+        msg: msg.to_string(),
+    }
+}
+
+fn data_err(path: &Path, msg: &str) -> CraycrayError {
+    CraycrayError::InvalidData {
+        file: path.display().to_string(),
+        msg: msg.to_string(),
+    }
+}
+
+// Parses the header lines up to `end_header`, also returns the number of
+// lines and the offset where the element data starts
+fn parse_header(
+    path: &Path,
+    data: &[u8],
+) -> Result<(Format, Vec<Element>, usize, usize), CraycrayError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    let mut line_no = 0;
+
+    loop {
+        let end = match data[pos..].iter().position(|&b| b == b'\n') {
+            Some(n) => pos + n,
+            None => return Err(parse_err(path, line_no + 1, "missing end_header")),
+        };
+        let line = str::from_utf8(&data[pos..end])
+            .map_err(|_| parse_err(path, line_no + 1, "invalid header"))?;
+        pos = end + 1;
+        line_no += 1;
+
+        let mut args = line.split_whitespace();
+        let keyword = match args.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        match keyword {
+            "ply" if line_no == 1 => {}
+            _ if line_no == 1 => return Err(parse_err(path, line_no, "not a PLY file")),
+            "format" => {
+                format = match args.next() {
+                    Some("ascii") => Some(Format::Ascii),
+                    Some("binary_little_endian") => Some(Format::LittleEndian),
+                    Some("binary_big_endian") => Some(Format::BigEndian),
+                    _ => return Err(parse_err(path, line_no, "unknown format")),
+                };
+            }
+            "element" => {
+                let name = args.next()
+                    .ok_or_else(|| parse_err(path, line_no, "missing element name"))?;
+                let count = args.next()
+                    .and_then(|c| c.parse().ok())
+                    .ok_or_else(|| parse_err(path, line_no, "invalid element count"))?;
+                elements.push(Element {
+                    name: name.to_string(),
+                    count: count,
+                    properties: Vec::new(),
+                });
+            }
+            "property" => {
+                let args: Vec<&str> = args.collect();
+                let scalar = |name: &str| {
+                    Scalar::parse(name)
+                        .ok_or_else(|| parse_err(path, line_no, "unknown property type"))
+                };
+                let (name, property) = match args.len() {
+                    2 => (args[1], Property::Scalar(scalar(args[0])?)),
+                    4 if args[0] == "list" => {
+                        (args[3], Property::List(scalar(args[1])?, scalar(args[2])?))
+                    }
+                    _ => return Err(parse_err(path, line_no, "invalid property")),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| parse_err(path, line_no, "property outside of an element"))?
+                    .properties
+                    .push((name.to_string(), property));
+            }
+            "end_header" => break,
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| parse_err(path, line_no, "missing format"))?;
+    Ok((format, elements, line_no, pos))
+}
+
+// Element data after the header, one item per line in ASCII files
+struct Body<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+    format: Format,
+    pos: usize,
+    line: usize,
+    tokens: Vec<&'a str>,
+}
+
+impl<'a> Body<'a> {
+    // Moves to the next item, only ASCII files need to know where it starts
+    fn next_item(&mut self) -> Result<(), CraycrayError> {
+        if self.format != Format::Ascii {
+            return Ok(());
+        }
+
+        loop {
+            if self.pos >= self.data.len() {
+                return Err(parse_err(self.path, self.line + 1, "unexpected end of file"));
+            }
+            let end = self.data[self.pos..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(self.data.len(), |n| self.pos + n);
+            let line = str::from_utf8(&self.data[self.pos..end])
+                .map_err(|_| parse_err(self.path, self.line + 1, "invalid text"))?;
+            self.pos = end + 1;
+            self.line += 1;
+
+            self.tokens = line.split_whitespace().rev().collect();
+            if !self.tokens.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read(&mut self, ty: Scalar) -> Result<f64, CraycrayError> {
+        if self.format == Format::Ascii {
+            let token = self.tokens
+                .pop()
+                .ok_or_else(|| parse_err(self.path, self.line, "missing values"))?;
+            return token
+                .parse()
+                .map_err(|_| parse_err(self.path, self.line, "invalid number"));
+        }
+
+        let end = self.pos + ty.size();
+        if end > self.data.len() {
+            return Err(data_err(self.path, "unexpected end of file"));
+        }
+        let value = ty.decode(&self.data[self.pos..end], self.format);
+        self.pos = end;
+        Ok(value)
+    }
+
+    // Reads all properties of an item, lists are flattened after their count
+    fn read_item(&mut self, element: &Element) -> Result<Vec<Vec<f64>>, CraycrayError> {
+        self.next_item()?;
+        let mut values = Vec::with_capacity(element.properties.len());
+        for &(_, ref property) in &element.properties {
+            match *property {
+                Property::Scalar(ty) => values.push(vec![self.read(ty)?]),
+                Property::List(count_ty, item_ty) => {
+                    let count = self.read(count_ty)?;
+                    if count < 0.0 {
+                        return Err(self.item_err("negative list length"));
+                    }
+                    let list = (0..count as usize)
+                        .map(|_| self.read(item_ty))
+                        .collect::<Result<Vec<f64>, _>>()?;
+                    values.push(list);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    fn item_err(&self, msg: &str) -> CraycrayError {
+        if self.format == Format::Ascii {
+            parse_err(self.path, self.line, msg)
+        } else {
+            data_err(self.path, msg)
+        }
+    }
+}
+
+// What a PLY file holds, normals and colors are empty if it has none
+struct PlyMesh {
+    vertices: Vec<Vec3d>,
+    normals: Vec<Vec3d>,
+    colors: Vec<Color>,
+    faces: Vec<Vec<usize>>,
+    creases: Vec<(usize, usize, f64)>,
+}
+
+// Reads the vertex, face and edge elements of the file contents
+fn parse_ply(path: &Path, data: &[u8]) -> Result<PlyMesh, CraycrayError> {
+    let (format, elements, header_lines, start) = parse_header(path, data)?;
+    let mut body = Body {
+        path: path,
+        data: data,
+        format: format,
+        pos: start,
+        line: header_lines,
+        tokens: Vec::new(),
+    };
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut faces = Vec::new();
+    let mut creases = Vec::new();
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let xyz = [&["x"], &["y"], &["z"]]
+                    .iter()
+                    .map(|names| element.property(*names))
+                    .collect::<Option<Vec<usize>>>()
+                    .ok_or_else(|| parse_err(path, header_lines, "vertex without position"))?;
+                let nxyz = [&["nx"], &["ny"], &["nz"]]
+                    .iter()
+                    .map(|names| element.property(*names))
+                    .collect::<Option<Vec<usize>>>();
+                let rgb = [
+                    &["red", "diffuse_red"],
+                    &["green", "diffuse_green"],
+                    &["blue", "diffuse_blue"],
+                ].iter()
+                    .map(|names| element.property(*names))
+                    .collect::<Option<Vec<usize>>>();
+                let color_max = rgb.as_ref().map(|rgb| {
+                    rgb.iter()
+                        .map(|&i| match element.properties[i].1 {
+                            Property::Scalar(ty) => ty.color_max(),
+                            Property::List(..) => 1.0,
+                        })
+                        .collect::<Vec<f64>>()
+                });
+
+                for _ in 0..element.count {
+                    let item = body.read_item(element)?;
+                    let value = |i: usize| item[i].first().cloned().unwrap_or(0.0);
+                    vertices.push(Vec3d::new(value(xyz[0]), value(xyz[1]), value(xyz[2])));
+                    if let Some(ref n) = nxyz {
+                        normals.push(Vec3d::new(value(n[0]), value(n[1]), value(n[2])));
+                    }
+                    if let (Some(c), Some(max)) = (rgb.as_ref(), color_max.as_ref()) {
+                        // Like 8 bit images, stored colors are sRGB
+                        let color = Color(
+                            value(c[0]) / max[0],
+                            value(c[1]) / max[1],
+                            value(c[2]) / max[2],
+                        );
+                        colors.push(color.from_srgb());
+                    }
+                }
+            }
+            "face" => {
+                let list = element
+                    .property(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| parse_err(path, header_lines, "face without vertex list"))?;
+                for _ in 0..element.count {
+                    let item = body.read_item(element)?;
+                    let face = &item[list];
+                    if face.len() < 3 {
+                        return Err(body.item_err("face needs at least 3 vertices"));
+                    }
+                    if face.iter().any(|&i| i < 0.0) {
+                        return Err(body.item_err("face index out of range"));
+                    }
+                    faces.push(face.iter().map(|&i| i as usize).collect::<Vec<usize>>());
+                }
+            }
+            "edge" => {
+                let props = [&["vertex1"], &["vertex2"], &["crease"]]
+                    .iter()
+                    .map(|names| element.property(*names))
+                    .collect::<Option<Vec<usize>>>();
+                for _ in 0..element.count {
+                    let item = body.read_item(element)?;
+                    if let Some(ref p) = props {
+                        let value = |i: usize| item[i].first().cloned().unwrap_or(0.0);
+                        let (a, b) = (value(p[0]), value(p[1]));
+                        if a < 0.0 || b < 0.0 {
+                            return Err(body.item_err("edge index out of range"));
+                        }
+                        creases.push((a as usize, b as usize, value(p[2])));
+                    }
+                }
+            }
+            _ => for _ in 0..element.count {
+                body.read_item(element)?;
+            },
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(data_err(path, "no faces"));
+    }
+    if faces.iter().any(|face| face.iter().any(|&i| i >= vertices.len())) {
+        return Err(data_err(path, "face index out of range"));
+    }
+    if let Some((a, b)) = stray_crease(faces.iter().map(|face| &face[..]), &creases) {
+        return Err(data_err(path, &format!("crease {}-{} is not an edge of a face", a, b)));
+    }
+
+    Ok(PlyMesh {
+        vertices: vertices,
+        normals: normals,
+        colors: colors,
+        faces: faces,
+        creases: creases,
+    })
+}
+
+/// Loads an ASCII or binary PLY file as a mesh, with the vertex normals and
+/// colors if it has them. Vertices are scaled then moved to `position`. The
+/// mesh is subdivided `levels` times, edge elements with a `crease` property
+/// mark the edges that stay sharp
+pub fn load_ply(
+    path: &Path,
+    position: Vec3d,
+    scale: f64,
+    material: Material,
+    levels: usize,
+) -> Result<Shape, CraycrayError> {
+    let f = File::open(path).map_err(CraycrayError::Io)?;
+    let mut data = Vec::new();
+    BufReader::new(f)
+        .read_to_end(&mut data)
+        .map_err(CraycrayError::Io)?;
+
+    let PlyMesh {
+        vertices,
+        normals,
+        colors,
+        faces,
+        creases,
+    } = parse_ply(path, &data)?;
+    let vertices = vertices.into_iter().map(|v| v * scale + position).collect();
+
+    if levels > 0 {
+        let mut control = ControlMesh::new(vertices, faces).with_colors(colors);
+        for (a, b, sharpness) in creases {
+            control.add_crease(a, b, sharpness);
+        }
+        return Ok(Shape::Mesh(control.subdivide(levels).into_mesh(material)));
+    }
+
+    let indices = fan_triangles(&faces);
+    let mesh = Mesh::from_normals(vertices, normals, indices, material).with_colors(colors);
+    Ok(Shape::Mesh(mesh))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 3\n\
+                          property float x\nproperty float y\nproperty float z\n\
+                          property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                          element face 1\nproperty list uchar int vertex_indices\n\
+                          end_header\n";
+    const POSITIONS: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.5, 0.0, 0.0], [0.0, -2.0, 0.25]];
+
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        for (i, p) in POSITIONS.iter().enumerate() {
+            for &x in p {
+                let bytes: Vec<u8> = (0..4).map(|k| (x.to_bits() >> (8 * k)) as u8).collect();
+                data.extend(if big_endian { bytes.into_iter().rev().collect() } else { bytes });
+            }
+            data.extend(&[if i == 0 { 255 } else { 0 }, 0, 0]);
+        }
+        data.push(3);
+        for i in 0..3u8 {
+            data.extend(if big_endian { [0, 0, 0, i] } else { [i, 0, 0, 0] }.iter());
+        }
+        data
+    }
+
+    fn check(data: &[u8]) {
+        let mesh = parse_ply(Path::new("test.ply"), data).unwrap();
+        let expected: Vec<Vec3d> = POSITIONS
+            .iter()
+            .map(|p| Vec3d::new(f64::from(p[0]), f64::from(p[1]), f64::from(p[2])))
+            .collect();
+        assert_eq!(mesh.vertices, expected);
+        assert_eq!(mesh.faces, vec![vec![0, 1, 2]]);
+        assert!(mesh.normals.is_empty());
+        let reds: Vec<f64> = mesh.colors.iter().map(|c| c.0).collect();
+        assert_eq!(reds, vec![1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(Scalar::Char.decode(&[0xff], Format::LittleEndian), -1.0);
+        assert_eq!(Scalar::UChar.decode(&[0xff], Format::BigEndian), 255.0);
+        assert_eq!(Scalar::Short.decode(&[0x00, 0x80], Format::LittleEndian), -32_768.0);
+        assert_eq!(Scalar::UShort.decode(&[0x01, 0x02], Format::BigEndian), 258.0);
+        assert_eq!(Scalar::Int.decode(&[0xfe, 0xff, 0xff, 0xff], Format::LittleEndian), -2.0);
+        assert_eq!(Scalar::Float.decode(&[0x3f, 0xc0, 0, 0], Format::BigEndian), 1.5);
+        let bytes = [0, 0, 0, 0, 0, 0, 0x04, 0xc0];
+        assert_eq!(Scalar::Double.decode(&bytes, Format::LittleEndian), -2.5);
+    }
+
+    #[test]
+    fn ascii() {
+        let data = format!(
+            "ply\nformat ascii 1.0\ncomment test\n{}0 0 0 255 0 0\n1.5 0 0 0 0 0\n\
+             0 -2 0.25 0 0 0\n\n3 0 1 2\n",
+            HEADER
+        );
+        check(data.as_bytes());
+    }
+
+    #[test]
+    fn little_endian() {
+        check(&binary("binary_little_endian", false));
+    }
+
+    #[test]
+    fn big_endian() {
+        check(&binary("binary_big_endian", true));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = binary("binary_little_endian", false);
+        assert!(parse_ply(Path::new("test.ply"), &data[..data.len() - 1]).is_err());
+        let data = "ply\nformat ascii 1.0\nelement face 1\nproperty list uchar int vertex_indices\n\
+                    end_header\n3 0 1 2\n";
+        assert!(parse_ply(Path::new("test.ply"), data.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn creases() {
+        let ply = |edge: &str| {
+            let data = format!(
+                "ply\nformat ascii 1.0\n{}element edge 1\n\
+                 property int vertex1\nproperty int vertex2\nproperty float crease\n\
+                 end_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n{}\n",
+                "element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                 element face 1\nproperty list uchar int vertex_indices\n",
+                edge
+            );
+            parse_ply(Path::new("test.ply"), data.as_bytes())
+        };
+        assert_eq!(ply("2 0 1.5").unwrap().creases, vec![(2, 0, 1.5)]);
+        match ply("0 3 1.5") {
+            Err(CraycrayError::InvalidData { .. }) => {}
+            _ => panic!("crease off the faces accepted"),
+        }
+    }
+}
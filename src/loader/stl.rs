@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::str;
+
+use cgmath::*;
+use vec3d::Vec3d;
+use material::Material;
+use shape::Shape;
+use shape::mesh::Mesh;
+use subdivision::ControlMesh;
+use scene::CraycrayError;
+
+// Binary files have an 80 byte header and a triangle count, then 50 bytes
+// per triangle
+const HEADER_SIZE: usize = 84;
+const TRIANGLE_SIZE: usize = 50;
+
+// Facet normal and corners
+type Facet = (Vec3d, [Vec3d; 3]);
+
+fn parse_err(path: &Path, line: usize, msg: &str) -> CraycrayError {
+    CraycrayError::Parse {
+        file: path.display().to_string(),
+        line: line,
+        msg: msg.to_string(),
+    }
+}
+
+fn read_f32(bytes: &[u8]) -> f64 {
+    let bits = bytes
+        .iter()
+        .rev()
+        .fold(0u32, |bits, &b| (bits << 8) | u32::from(b));
+    f64::from(f32::from_bits(bits))
+}
+
+fn read_vec(bytes: &[u8]) -> Vec3d {
+    Vec3d::new(read_f32(&bytes[0..4]), read_f32(&bytes[4..8]), read_f32(&bytes[8..12]))
+}
+
+// Triangle count of a binary file, the data has at least the header
+fn triangle_count(data: &[u8]) -> usize {
+    data[80..HEADER_SIZE]
+        .iter()
+        .rev()
+        .fold(0, |n, &b| (n << 8) | b as usize)
+}
+
+fn binary_facets(path: &Path, data: &[u8]) -> Result<Vec<Facet>, CraycrayError> {
+    let count = triangle_count(data);
+    if data.len() < HEADER_SIZE + count * TRIANGLE_SIZE {
+        return Err(CraycrayError::InvalidData {
+            file: path.display().to_string(),
+            msg: format!("{} bytes are too short for {} triangles", data.len(), count),
+        });
+    }
+
+    Ok(data[HEADER_SIZE..HEADER_SIZE + count * TRIANGLE_SIZE]
+        .chunks(TRIANGLE_SIZE)
+        .map(|tri| {
+            let corners = [read_vec(&tri[12..24]), read_vec(&tri[24..36]), read_vec(&tri[36..48])];
+            (read_vec(&tri[0..12]), corners)
+        })
+        .collect())
+}
+
+fn parse_vec<'a, I>(path: &Path, line: usize, args: I) -> Result<Vec3d, CraycrayError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let v = args.take(3)
+        .map(|a| a.parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| parse_err(path, line, "invalid number"))?;
+    if v.len() == 3 {
+        Ok(Vec3d::new(v[0], v[1], v[2]))
+    } else {
+        Err(parse_err(path, line, "missing values"))
+    }
+}
+
+fn ascii_facets(path: &Path, text: &str) -> Result<Vec<Facet>, CraycrayError> {
+    let mut facets = Vec::new();
+    let mut normal = Vec3d::zero();
+    let mut corners = Vec::new();
+
+    for (line_no, line) in text.lines().enumerate() {
+        let line_no = line_no + 1;
+        let mut args = line.split_whitespace();
+        match args.next() {
+            Some("facet") => {
+                if args.next() != Some("normal") {
+                    return Err(parse_err(path, line_no, "expected facet normal"));
+                }
+                normal = parse_vec(path, line_no, args)?;
+                corners.clear();
+            }
+            Some("vertex") => corners.push(parse_vec(path, line_no, args)?),
+            Some("endfacet") => {
+                if corners.len() != 3 {
+                    return Err(parse_err(path, line_no, "facet needs 3 vertices"));
+                }
+                facets.push((normal, [corners[0], corners[1], corners[2]]));
+            }
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+// Facets of the file contents in either format
+fn parse_stl(path: &Path, data: &[u8]) -> Result<Vec<Facet>, CraycrayError> {
+    // Binary files may start with "solid" too, their size gives them away
+    let binary_size = if data.len() >= HEADER_SIZE {
+        Some(HEADER_SIZE + triangle_count(data) * TRIANGLE_SIZE)
+    } else {
+        None
+    };
+    let text = if data.starts_with(b"solid") && binary_size != Some(data.len()) {
+        str::from_utf8(data).ok()
+    } else {
+        None
+    };
+    let facets = match (text, binary_size) {
+        (Some(text), _) => ascii_facets(path, text)?,
+        (None, Some(_)) => binary_facets(path, data)?,
+        (None, None) => {
+            return Err(CraycrayError::InvalidData {
+                file: path.display().to_string(),
+                msg: "not an STL file".to_string(),
+            })
+        }
+    };
+
+    if facets.is_empty() {
+        return Err(CraycrayError::InvalidData {
+            file: path.display().to_string(),
+            msg: "no facets".to_string(),
+        });
+    }
+    Ok(facets)
+}
+
+/// Loads an ASCII or binary STL file as a mesh, vertices are scaled then
+/// moved to `position`. Facets stay flat, unless the mesh is subdivided
+/// `levels` times, which merges the equal corners of neighbouring facets
+pub fn load_stl(
+    path: &Path,
+    position: Vec3d,
+    scale: f64,
+    material: Material,
+    levels: usize,
+) -> Result<Shape, CraycrayError> {
+    let f = File::open(path).map_err(CraycrayError::Io)?;
+    let mut data = Vec::new();
+    BufReader::new(f)
+        .read_to_end(&mut data)
+        .map_err(CraycrayError::Io)?;
+    let facets = parse_stl(path, &data)?;
+    Ok(facets_to_shape(facets, position, scale, material, levels))
+}
+
+// Unit facet normal and whether the corners wind the other way. Stored
+// normals win over the winding, which some exporters get wrong, but a zero
+// one means the winding is all there is
+fn facet_normal(stored: Vec3d, corners: &[Vec3d; 3]) -> (Vec3d, bool) {
+    let winding = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+    if stored.magnitude2() > 0.0 {
+        (stored.normalize(), winding.dot(stored) < 0.0)
+    } else if winding.magnitude2() > 0.0 {
+        (winding.normalize(), false)
+    } else {
+        (winding, false)
+    }
+}
+
+fn facets_to_shape(
+    facets: Vec<Facet>,
+    position: Vec3d,
+    scale: f64,
+    material: Material,
+    levels: usize,
+) -> Shape {
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut welded = HashMap::new();
+    let mut indices = Vec::with_capacity(facets.len());
+    for (stored, corners) in facets {
+        let (normal, flipped) = facet_normal(stored, &corners);
+        let mut tri = [0; 3];
+        for (dst, v) in tri.iter_mut().zip(corners.iter()) {
+            if levels > 0 {
+                let key = (v.x.to_bits(), v.y.to_bits(), v.z.to_bits());
+                *dst = *welded.entry(key).or_insert_with(|| {
+                    vertices.push(*v * scale + position);
+                    vertices.len() - 1
+                });
+            } else {
+                // Every facet gets its own corners to keep the edges hard
+                vertices.push(*v * scale + position);
+                normals.push(normal);
+                *dst = vertices.len() - 1;
+            }
+        }
+        if flipped {
+            tri.swap(1, 2);
+        }
+        indices.push(tri);
+    }
+
+    if levels > 0 {
+        let faces = indices.iter().map(|tri| tri.to_vec()).collect();
+        let control = ControlMesh::new(vertices, faces).subdivide(levels);
+        return Shape::Mesh(control.into_mesh(material));
+    }
+    Shape::Mesh(Mesh::from_normals(vertices, normals, indices, material))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use material::MIRROR;
+    use shape::Intersectable;
+
+    const CORNERS: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.5]];
+
+    fn to_vec(p: &[f32; 3]) -> Vec3d {
+        Vec3d::new(f64::from(p[0]), f64::from(p[1]), f64::from(p[2]))
+    }
+
+    // Binary file of one facet, padded to the header size from `header`
+    fn binary(header: &[u8]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, b' ');
+        data.extend(&[1, 0, 0, 0]);
+        let normal = [0.0f32, 0.0, 1.0];
+        for &x in normal.iter().chain(CORNERS.iter().flat_map(|c| c.iter())) {
+            data.extend((0..4).map(|k| (x.to_bits() >> (8 * k)) as u8));
+        }
+        data.extend(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn read_binary_values() {
+        assert_eq!(read_f32(&[0, 0, 0xc0, 0x3f]), 1.5);
+        assert_eq!(read_f32(&[0, 0, 0x20, 0xc1]), -10.0);
+        let mut data = vec![0; 80];
+        data.extend(&[0x02, 0x01, 0, 0]);
+        assert_eq!(triangle_count(&data), 258);
+    }
+
+    #[test]
+    fn ascii() {
+        let text = "solid test\n\
+                    facet normal 0 0 1\n\
+                    outer loop\n\
+                    vertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0.5\n\
+                    endloop\n\
+                    endfacet\n\
+                    endsolid test\n";
+        let facets = parse_stl(Path::new("test.stl"), text.as_bytes()).unwrap();
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facets[0].0, Vec3d::new(0.0, 0.0, 1.0));
+        assert_eq!(facets[0].1.to_vec(), CORNERS.iter().map(to_vec).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn binary_starting_with_solid() {
+        for header in &[&b"binary"[..], &b"solid exported"[..]] {
+            let facets = parse_stl(Path::new("test.stl"), &binary(header)).unwrap();
+            assert_eq!(facets.len(), 1);
+            assert_eq!(facets[0].0, Vec3d::new(0.0, 0.0, 1.0));
+            assert_eq!(facets[0].1.to_vec(), CORNERS.iter().map(to_vec).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let data = binary(b"solid exported");
+        assert!(parse_stl(Path::new("test.stl"), &data[..data.len() - 1]).is_err());
+        assert!(parse_stl(Path::new("test.stl"), b"solid empty\nendsolid empty\n").is_err());
+    }
+
+    #[test]
+    fn flat_facets() {
+        // Two facets folded along the y axis, the second without a normal
+        let (o, y) = (Vec3d::zero(), Vec3d::new(0.0, 1.0, 0.0));
+        let facets = vec![
+            (Vec3d::new(0.0, 0.0, 2.0), [o, Vec3d::new(1.0, 0.0, 0.0), y]),
+            (Vec3d::zero(), [o, y, Vec3d::new(0.0, 0.0, 1.0)]),
+        ];
+        let shape = facets_to_shape(facets, Vec3d::zero(), 1.0, MIRROR, 0);
+
+        let top = shape
+            .intersect(Vec3d::new(0.1, 0.2, 5.0), Vec3d::new(0.0, 0.0, -1.0))
+            .expect("first facet hit");
+        assert_eq!(top.normal, Vec3d::new(0.0, 0.0, 1.0));
+        let side = shape
+            .intersect(Vec3d::new(5.0, 0.2, 0.1), Vec3d::new(-1.0, 0.0, 0.0))
+            .expect("second facet hit");
+        assert_eq!(side.normal, Vec3d::new(1.0, 0.0, 0.0));
+    }
+}
@@ -2,160 +2,136 @@
 extern crate clap;
 extern crate craycray;
 extern crate rayon;
+#[cfg(feature = "viewer")]
 extern crate sdl2;
 
-use std::time::Instant;
+#[cfg(feature = "viewer")]
+mod viewer;
 
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
-use sdl2::EventPump;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+#[cfg(not(feature = "viewer"))]
+use std::process;
+
+use clap::ArgMatches;
 
 use rayon::prelude::*;
 
 use craycray::scene::Scene;
+use craycray::sampling::Sampling;
+use craycray::tonemap::{ToneMap, ToneMapper};
+
+#[cfg(feature = "viewer")]
+use viewer::view;
+
+// Fill an RGB24 buffer with rendered lines
+fn draw(
+    scene: &Scene,
+    tone_mapper: &ToneMapper,
+    width: usize,
+    height: usize,
+    buffer: &mut [u8],
+    pitch: usize,
+) {
+    let pchunks = buffer.par_chunks_mut(pitch);
+    pchunks.enumerate().for_each(|(line_no, chunk)| {
+        let line_iter = scene.line_iter(width, height, line_no);
+        for (c, pix) in line_iter.zip(chunk.chunks_mut(3)) {
+            let (r, g, b) = tone_mapper.to_rgb(c);
+            pix[0] = r;
+            pix[1] = g;
+            pix[2] = b;
+        }
+    });
+}
 
-struct FpsCounter {
-    ts: Instant,
-    cnt: usize,
-    freq: usize,
+fn parse_size(matches: &ArgMatches, name: &str, default: usize) -> usize {
+    matches
+        .value_of(name)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
 }
 
-impl FpsCounter {
-    pub fn new(freq: usize) -> Self {
-        let cnt = 0;
-        let ts = Instant::now();
-        FpsCounter { ts, cnt, freq }
+// Apply the scene settings given on the command line
+fn configure(scene: &mut Scene, matches: &ArgMatches) {
+    if let Some(samples) = matches.value_of("SAMPLES").and_then(|v| v.parse().ok()) {
+        let sampling = if matches.is_present("JITTER") {
+            Sampling::Jittered(samples)
+        } else {
+            Sampling::Grid(samples)
+        };
+        scene.set_sampling(sampling);
     }
 
-    pub fn update(&mut self) {
-        if self.cnt >= self.freq {
-            let elapsed = self.ts.elapsed();
-            let msecs = (u64::from(elapsed.as_secs()) * 1000) +
-                (u64::from(elapsed.subsec_nanos()) / 1_000_000);
-            self.ts = Instant::now();
-            eprintln!("FPS {}", (self.cnt as f32) / (msecs as f32 / 1000.0));
-            self.cnt = 0;
-        }
-
-        self.cnt += 1;
+    if let Some(fov) = matches.value_of("FOV").and_then(|v| v.parse().ok()) {
+        scene.set_fov(fov);
     }
 }
 
-// Return true for quit
-fn handle_events(scene: &mut Scene, event_pump: &mut EventPump) -> bool {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. } |
-            Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => return true,
-            Event::KeyDown {
-                keycode: Some(Keycode::W),
-                ..
-            } => {
-                scene.mv_camera_fwd();
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => {
-                scene.mv_camera_back();
-            }
-            _ => {}
-        }
-    }
+fn is_tone_map(v: String) -> Result<(), String> {
+    v.parse::<ToneMap>().map(|_| ())
+}
 
-    let rel_mouse = event_pump.relative_mouse_state();
+fn tone_mapper(matches: &ArgMatches) -> ToneMapper {
+    let operator = matches
+        .value_of("TONEMAP")
+        .map_or(ToneMap::Clamp, |v| v.parse().unwrap());
+    let exposure = matches
+        .value_of("EXPOSURE")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0.0);
+    ToneMapper::new(operator, exposure)
+}
 
-    let x_rot = f64::from(rel_mouse.x()) * (0.010);
-    let y_rot = f64::from(rel_mouse.y()) as f64 * (0.010);
-    scene.rot_camera(x_rot, y_rot);
-    false
+// Render a single frame to a file, doesn't need a display
+fn render(matches: &ArgMatches) {
+    let scene_file = matches.value_of("SCENE").unwrap_or("scene.json");
+    let output = matches.value_of("OUTPUT").unwrap();
+    let width = parse_size(matches, "WIDTH", 512);
+    let height = parse_size(matches, "HEIGHT", width);
+
+    let mut scene = Scene::from_file(scene_file).unwrap();
+    configure(&mut scene, matches);
+    let mut buffer = vec![0; width * height * 3];
+    draw(&scene, &tone_mapper(matches), width, height, &mut buffer, width * 3);
+    craycray::output::save(output, width, height, &buffer).unwrap();
 }
 
-// A simple test code that uses SDL for rendering
 fn main() {
     let matches = clap_app!(craycray =>
         (version: "0.1")
         (about: "craycray")
-        (@arg RESOLUTION: -r --resolution +takes_value "Render resolution")
+        (@arg RESOLUTION: -r --resolution +takes_value "Render width")
+        (@arg HEIGHT: -H --height +takes_value "Render height, defaults to width")
+        (@arg FOV: --fov +takes_value "Vertical field of view in degrees")
+        (@arg EXPOSURE: -e --exposure +takes_value "Exposure adjustment in stops")
+        (@arg TONEMAP: -t --tonemap +takes_value {is_tone_map}
+            "Tone mapping: clamp, reinhard, reinhard-extended[:white] or aces")
         (@arg FULLSCREEN: -f --fullscreen "Fullscreen")
+        (@arg SAMPLES: -a --samples +takes_value "Supersample every pixel with an NxN grid")
+        (@arg JITTER: -j --jitter requires[SAMPLES] "Jitter the supersampling grid")
+        (@subcommand render =>
+            (about: "Render a single frame to a PNG or PPM file")
+            (@arg SCENE: -s --scene +takes_value "Scene file, defaults to scene.json")
+            (@arg WIDTH: -W --width +takes_value "Image width")
+            (@arg HEIGHT: -H --height +takes_value "Image height, defaults to width")
+            (@arg FOV: --fov +takes_value "Vertical field of view in degrees")
+            (@arg EXPOSURE: -e --exposure +takes_value "Exposure adjustment in stops")
+            (@arg TONEMAP: -t --tonemap +takes_value {is_tone_map}
+                "Tone mapping: clamp, reinhard, reinhard-extended[:white] or aces")
+            (@arg SAMPLES: -a --samples +takes_value "Supersample every pixel with an NxN grid")
+            (@arg JITTER: -j --jitter requires[SAMPLES] "Jitter the supersampling grid")
+            (@arg OUTPUT: +required "Output file, format is picked from the extension")
+        )
     ).get_matches();
 
-
-    let fullscreen = matches.is_present("FULLSCREEN");
-    let resolution: u32 = matches
-        .value_of("RESOLUTION")
-        .unwrap_or("512")
-        .parse()
-        .unwrap_or(512);
-    let res_u = resolution as usize;
-    let mut scene = Scene::from_file("scene.json").unwrap();
-
-    // Stuff from sdl2-rust example
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-
-    let window = if fullscreen {
-        let display_mode = video_subsystem.desktop_display_mode(0).unwrap();
-        video_subsystem
-            .window("craycray", display_mode.w as u32, display_mode.h as u32)
-            .fullscreen_desktop()
-            .build()
-            .unwrap()
+    if let Some(render_matches) = matches.subcommand_matches("render") {
+        render(render_matches);
     } else {
-        video_subsystem
-            .window("craycray", resolution, resolution)
-            .position_centered()
-            .build()
-            .unwrap()
-    };
-
-    let (_, window_h) = window.size();
-
-    let mut canvas = window.into_canvas().build().unwrap();
-    let texture_creator = canvas.texture_creator();
-
-    let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, resolution, resolution)
-        .unwrap();
-    sdl_context.mouse().show_cursor(false);
-    sdl_context.mouse().set_relative_mouse_mode(true);
-
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut fps = FpsCounter::new(10);
-
-    loop {
-        let quit = handle_events(&mut scene, &mut event_pump);
-        if quit {
-            break;
-        }
-
-        scene.step();
-
-        texture
-            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                let pchunks = buffer.par_chunks_mut(pitch);
-                pchunks.enumerate().for_each(|(line_no, chunk)| {
-                    let line_iter = scene.line_iter(res_u, res_u, line_no);
-                    for (c, pix) in line_iter.zip(chunk.chunks_mut(3)) {
-                        let (r, g, b) = c.into();
-                        pix[0] = r;
-                        pix[1] = g;
-                        pix[2] = b;
-                    }
-                });
-            })
-            .unwrap();
-        canvas.clear();
-        canvas
-            .copy(&texture, None, Some(Rect::new(0, 0, window_h, window_h)))
-            .unwrap();
-        canvas.present();
-
-        fps.update();
+        view(&matches);
     }
 }
+
+#[cfg(not(feature = "viewer"))]
+fn view(_matches: &ArgMatches) {
+    eprintln!("craycray was built without the viewer feature, use the render subcommand");
+    process::exit(1);
+}
@@ -1,13 +1,27 @@
 use color;
 use color::Color;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Material {
     pub ambient_color: Color,
     pub specular_color: Color,
     pub diffuse_color: Color,
     pub shininess: f64,
     pub reflectivity: f64,
+    /// Fraction of light passing through the surface, shadows are lightened
+    /// by it too
+    #[serde(default)]
+    pub transparency: f64,
+    /// Index of refraction of the material's interior
+    #[serde(default = "default_ior")]
+    pub ior: f64,
+    /// Diffuse color blended in by the orbit trap of fractal surfaces
+    #[serde(default)]
+    pub trap_color: Option<Color>,
+}
+
+fn default_ior() -> f64 {
+    1.0
 }
 
 pub const MIRROR: Material = Material {
@@ -16,4 +30,18 @@ pub const MIRROR: Material = Material {
     diffuse_color: color::BLACK,
     shininess: 0.0,
     reflectivity: 1.0,
+    transparency: 0.0,
+    ior: 1.0,
+    trap_color: None,
+};
+
+pub const GLASS: Material = Material {
+    ambient_color: color::BLACK,
+    specular_color: color::WHITE,
+    diffuse_color: color::BLACK,
+    shininess: 50.0,
+    reflectivity: 0.0,
+    transparency: 1.0,
+    ior: 1.5,
+    trap_color: None,
 };
@@ -0,0 +1,49 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use png;
+use png::HasParameters;
+
+use scene::CraycrayError;
+
+/// Writes packed 8-bit RGB pixels as a binary PPM
+pub fn write_ppm<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> Result<(), CraycrayError> {
+    write!(w, "P6\n{} {}\n255\n", width, height).map_err(CraycrayError::Io)?;
+    w.write_all(rgb).map_err(CraycrayError::Io)
+}
+
+/// Writes packed 8-bit RGB pixels as a PNG
+pub fn write_png<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> Result<(), CraycrayError> {
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgb))
+        .map_err(CraycrayError::Png)
+}
+
+/// Saves the image, the format is picked from the file extension (png or ppm)
+pub fn save(filename: &str, width: usize, height: usize, rgb: &[u8]) -> Result<(), CraycrayError> {
+    let is_ppm = Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("ppm"));
+
+    let mut w = BufWriter::new(File::create(filename).map_err(CraycrayError::Io)?);
+    if is_ppm {
+        write_ppm(&mut w, width, height, rgb)
+    } else {
+        write_png(&mut w, width, height, rgb)
+    }
+}
@@ -0,0 +1,174 @@
+//! Real roots of low degree polynomials, coefficients go from the highest
+//! degree down and roots are returned in ascending order
+
+use std::f64;
+use std::f64::consts::PI;
+
+const EPSILON: f64 = 1e-12;
+const POLISH_STEPS: usize = 4;
+
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < EPSILON {
+        return if b.abs() < EPSILON { vec![] } else { vec![-c / b] };
+    }
+
+    let delta = b * b - 4.0 * a * c;
+    if delta < 0.0 {
+        return vec![];
+    }
+
+    // Avoids cancellation between -b and the square root
+    let q = -0.5 * (b + b.signum() * delta.sqrt());
+    let mut roots = if q == 0.0 {
+        vec![0.0, 0.0]
+    } else {
+        vec![q / a, c / q]
+    };
+    sort(&mut roots);
+    roots
+}
+
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a.abs() < EPSILON {
+        return solve_quadratic(b, c, d);
+    }
+
+    let (b, c, d) = (b / a, c / a, d / a);
+    // Depressed cubic w^3 + p w + q with z = w - b / 3
+    let shift = b / 3.0;
+    let p = c - b * b / 3.0;
+    let q = 2.0 * b * b * b / 27.0 - b * c / 3.0 + d;
+    let disc = (q / 2.0).powi(2) + (p / 3.0).powi(3);
+
+    let mut roots = if disc > 0.0 {
+        let sq = disc.sqrt();
+        vec![(-q / 2.0 + sq).cbrt() + (-q / 2.0 - sq).cbrt()]
+    } else if p.abs() < EPSILON {
+        vec![(-q).cbrt()]
+    } else {
+        let m = 2.0 * (-p / 3.0).sqrt();
+        let arg = ((3.0 * q) / (p * m)).clamp(-1.0, 1.0);
+        let theta = arg.acos() / 3.0;
+        (0..3)
+            .map(|k| m * (theta - 2.0 * PI * k as f64 / 3.0).cos())
+            .collect()
+    };
+
+    for r in &mut roots {
+        *r = polish(&[1.0, b, c, d], *r - shift);
+    }
+    sort(&mut roots);
+    roots
+}
+
+/// Solved by factoring the depressed quartic into two quadratics, the roots
+/// are refined with Newton steps on the original polynomial to suppress the
+/// cancellation errors of grazing rays
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a.abs() < EPSILON {
+        return solve_cubic(b, c, d, e);
+    }
+
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    // Depressed quartic y^4 + p y^2 + q y + r with x = y - b / 4
+    let shift = b / 4.0;
+    let b2 = b * b;
+    let p = c - 3.0 * b2 / 8.0;
+    let q = d - b * c / 2.0 + b2 * b / 8.0;
+    let r = e - b * d / 4.0 + b2 * c / 16.0 - 3.0 * b2 * b2 / 256.0;
+
+    let mut roots = if q.abs() < EPSILON {
+        // Biquadratic, a quadratic in y^2
+        solve_quadratic(1.0, p, r)
+            .into_iter()
+            .filter(|&y2| y2 >= 0.0)
+            .flat_map(|y2| vec![-y2.sqrt(), y2.sqrt()])
+            .collect()
+    } else {
+        // (y^2 + s y + u)(y^2 - s y + v), s^2 is a positive root of the resolvent
+        let z = solve_cubic(1.0, 2.0 * p, p * p - 4.0 * r, -q * q)
+            .into_iter()
+            .fold(0.0f64, f64::max);
+        if z <= 0.0 {
+            return vec![];
+        }
+        let s = z.sqrt();
+        let u = (p + z - q / s) / 2.0;
+        let v = (p + z + q / s) / 2.0;
+
+        let mut ys = solve_quadratic(1.0, s, u);
+        ys.extend(solve_quadratic(1.0, -s, v));
+        ys
+    };
+
+    for x in &mut roots {
+        *x = polish(&[1.0, b, c, d, e], *x - shift);
+    }
+    sort(&mut roots);
+    roots
+}
+
+// Evaluates the polynomial and its derivative with Horner's scheme
+fn eval(coeffs: &[f64], x: f64) -> (f64, f64) {
+    coeffs.iter().fold((0.0, 0.0), |(f, df), &c| (f * x + c, df * x + f))
+}
+
+fn polish(coeffs: &[f64], mut x: f64) -> f64 {
+    for _ in 0..POLISH_STEPS {
+        let (f, df) = eval(coeffs, x);
+        if df.abs() < EPSILON {
+            break;
+        }
+        let next = x - f / df;
+        // Keep the original estimate if the step makes things worse
+        if eval(coeffs, next).0.abs() >= f.abs() {
+            break;
+        }
+        x = next;
+    }
+    x
+}
+
+fn sort(roots: &mut [f64]) {
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+        assert_eq!(roots.len(), expected.len(), "roots {:?}", roots);
+        for (r, e) in roots.iter().zip(expected) {
+            assert!((r - e).abs() < 1e-9, "roots {:?}, expected {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn quadratic() {
+        assert_roots(solve_quadratic(1.0, -4.0, 3.0), &[1.0, 3.0]);
+        assert_roots(solve_quadratic(2.0, 0.0, -8.0), &[-2.0, 2.0]);
+        assert_roots(solve_quadratic(1.0, 0.0, 1.0), &[]);
+        assert_roots(solve_quadratic(0.0, 2.0, -1.0), &[0.5]);
+    }
+
+    #[test]
+    fn cubic() {
+        // (x - 1)(x - 2)(x - 3)
+        assert_roots(solve_cubic(1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+        // (x + 2)(x^2 + 1)
+        assert_roots(solve_cubic(1.0, 2.0, 1.0, 2.0), &[-2.0]);
+        assert_roots(solve_cubic(2.0, 0.0, 0.0, -16.0), &[2.0]);
+    }
+
+    #[test]
+    fn quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0]);
+        // (x^2 - 1)(x^2 - 4)
+        assert_roots(solve_quartic(1.0, 0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0]);
+        // (x + 1)(x - 2)(x^2 + 1)
+        assert_roots(solve_quartic(3.0, -3.0, -3.0, -3.0, -6.0), &[-1.0, 2.0]);
+        assert_roots(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0), &[]);
+    }
+}
@@ -0,0 +1,83 @@
+/// Supersampling pattern used for every pixel
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Sampling {
+    /// N×N samples on a regular grid
+    Grid(usize),
+    /// N×N samples, each placed randomly inside its grid cell
+    Jittered(usize),
+}
+
+impl Default for Sampling {
+    fn default() -> Sampling {
+        Sampling::Grid(1)
+    }
+}
+
+impl Sampling {
+    /// Sub-pixel offsets in pixel units, centered on the pixel's sample point.
+    /// `seed` picks the jitter so neighbouring pixels don't share a pattern
+    pub fn offsets(&self, seed: u64) -> Offsets {
+        let (n, jittered) = match *self {
+            Sampling::Grid(n) => (n.max(1), false),
+            Sampling::Jittered(n) => (n.max(1), true),
+        };
+        Offsets {
+            n: n,
+            jittered: jittered,
+            rng: XorShift::new(seed),
+            next: 0,
+        }
+    }
+}
+
+/// Sub-pixel offsets of one pixel, row by row
+pub struct Offsets {
+    n: usize,
+    jittered: bool,
+    rng: XorShift,
+    next: usize,
+}
+
+impl Iterator for Offsets {
+    type Item = (f64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.n * self.n {
+            return None;
+        }
+        let (sx, sy) = (self.next % self.n, self.next / self.n);
+        self.next += 1;
+
+        let (jx, jy) = if self.jittered {
+            (self.rng.next_f64(), self.rng.next_f64())
+        } else {
+            (0.5, 0.5)
+        };
+        let cell = 1.0 / self.n as f64;
+        Some(((sx as f64 + jx) * cell - 0.5, (sy as f64 + jy) * cell - 0.5))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.n * self.n - self.next;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Offsets {}
+
+// Small xorshift64* generator, plenty for jitter
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let r = self.0.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (r >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -1,14 +1,28 @@
 use std::io;
 use std::io::BufReader;
 use std::fs::File;
+use std::path::Path;
+use std::slice;
+use std::iter::{Chain, Cloned};
+use std::ops::Range;
+use std::mem;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 use serde_json;
+use png;
 
 use cgmath::*;
 use shape::*;
+use shape::transformed::{deserialize_prototypes, serialize_prototypes};
 use vec3d::Vec3d;
 use vec3d::Rotatable;
 use light::Light;
+use bvh::Bvh;
+use transform::Transform;
+use loader::Model;
+use sampling::Sampling;
+use material::Material;
 
 use color;
 use color::Color;
@@ -16,45 +30,259 @@ use color::Color;
 #[derive(Serialize, Deserialize)]
 pub struct Scene {
     shapes: Vec<Shape>,
+    /// Named shapes that `Transformed` shapes can instance without copying,
+    /// also inside other prototypes. They can't be animated
+    #[serde(default, serialize_with = "serialize_prototypes",
+            deserialize_with = "deserialize_prototypes")]
+    prototypes: HashMap<String, Arc<Shape>>,
     lights: Vec<Light>,
     camera_pos: Vec3d,
     camera_dir: Vec3d,
     camera_up: Vec3d,
+    /// Vertical field of view in degrees
+    #[serde(default = "default_fov")]
+    camera_fov: f64,
+    /// Width / height of the image plane, None follows the render resolution
+    #[serde(default)]
+    camera_aspect: Option<f64>,
     max_reflection: i32,
+    #[serde(default)]
+    models: Vec<Model>,
+    #[serde(default)]
+    sampling: Sampling,
+    /// Colors in the file are sRGB encoded and get decoded to linear on load.
+    /// Vertex colors of PLY models are always taken as sRGB
+    #[serde(default)]
+    srgb_colors: bool,
+    #[serde(skip)]
+    bvh: Bvh,
+    // Shapes without bounds, tested against every ray
+    #[serde(skip)]
+    unbounded: Vec<usize>,
+    // Number of leading shapes covered by `bvh` or `unbounded`
+    #[serde(skip)]
+    bvh_len: usize,
+    // Animation time in seconds
+    #[serde(skip)]
+    time: f64,
+}
+
+// Animation time that passes with every `step`
+const STEP_TIME: f64 = 1.0 / 30.0;
+
+fn default_fov() -> f64 {
+    90.0
 }
 
 #[derive(Debug)]
 pub enum CraycrayError {
     Io(io::Error),
     Serde(serde_json::Error),
+    Png(png::EncodingError),
+    PngDecode(png::DecodingError),
+    Parse {
+        file: String,
+        line: usize,
+        msg: String,
+    },
+    /// A shape references a prototype the scene doesn't define
+    UnknownPrototype(String),
+    /// A shape of the scene file with inconsistent data
+    InvalidShape(String),
+    /// Malformed or empty model data
+    InvalidData { file: String, msg: String },
+    /// A model file of a format no loader reads
+    UnknownFormat(String),
 }
 
 impl Scene {
     pub fn new(camera_pos: Vec3d, camera_dir: Vec3d, camera_up: Vec3d) -> Scene {
         Scene {
             shapes: Vec::new(),
+            prototypes: HashMap::new(),
             lights: Vec::new(),
             camera_pos: camera_pos,
             camera_dir: camera_dir,
             camera_up: camera_up,
+            camera_fov: default_fov(),
+            camera_aspect: None,
             max_reflection: 4,
+            models: Vec::new(),
+            sampling: Sampling::default(),
+            srgb_colors: false,
+            bvh: Bvh::default(),
+            unbounded: Vec::new(),
+            bvh_len: 0,
+            time: 0.0,
         }
     }
 
     pub fn from_file(filename: &str) -> Result<Scene, CraycrayError> {
-        File::open(filename)
+        let mut scene: Scene = File::open(filename)
             .map_err(CraycrayError::Io)
             .and_then(|f| {
                 serde_json::from_reader(BufReader::new(f)).map_err(CraycrayError::Serde)
-            })
+            })?;
+
+        let base_dir = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+        scene.load_models(base_dir)?;
+        scene.load_resources(base_dir)?;
+        if scene.srgb_colors {
+            scene.decode_srgb_colors();
+        }
+        scene.resolve_prototypes()?;
+        scene.build_bvh();
+        Ok(scene)
+    }
+
+    // Append the meshes of all referenced models to the shapes
+    fn load_models(&mut self, base_dir: &Path) -> Result<(), CraycrayError> {
+        for model in &self.models {
+            let shapes = model.load(base_dir)?;
+            self.shapes.extend(shapes);
+        }
+        Ok(())
+    }
+
+    // Read the images and other files the shapes refer to
+    fn load_resources(&mut self, base_dir: &Path) -> Result<(), CraycrayError> {
+        for s in &mut self.shapes {
+            s.load_resources(base_dir)?;
+        }
+        for p in self.prototypes.values_mut() {
+            if let Some(s) = Arc::get_mut(p) {
+                s.load_resources(base_dir)?;
+            }
+        }
+        Ok(())
     }
 
+    // Link the instances to the prototypes they name
+    fn resolve_prototypes(&mut self) -> Result<(), CraycrayError> {
+        // Shared shapes can't be changed, so they can't move either
+        for (name, p) in &self.prototypes {
+            if p.is_animated() {
+                return Err(CraycrayError::InvalidShape(format!(
+                    "prototype {} is animated",
+                    name
+                )));
+            }
+        }
+
+        // Prototypes may instance each other, the ones they use go first
+        let mut pending = mem::take(&mut self.prototypes);
+        let names = pending.keys().cloned().collect::<Vec<_>>();
+        for name in names {
+            resolve_prototype(&name, &mut pending, &mut self.prototypes, &mut Vec::new())?;
+        }
+
+        for s in &mut self.shapes {
+            s.resolve_prototypes(&self.prototypes)
+                .map_err(CraycrayError::UnknownPrototype)?;
+        }
+        Ok(())
+    }
+
+    // Convert all material and light colors from sRGB to linear
+    fn decode_srgb_colors(&mut self) {
+        let mut decode = |m: &mut Material| {
+            m.ambient_color = m.ambient_color.from_srgb();
+            m.specular_color = m.specular_color.from_srgb();
+            m.diffuse_color = m.diffuse_color.from_srgb();
+            m.trap_color = m.trap_color.map(|c| c.from_srgb());
+        };
+        for s in &mut self.shapes {
+            s.map_materials(&mut decode);
+        }
+        for p in self.prototypes.values_mut() {
+            if let Some(s) = Arc::get_mut(p) {
+                s.map_materials(&mut decode);
+            }
+        }
+
+        for l in &mut self.lights {
+            let c = l.get_color().from_srgb();
+            l.set_color(c);
+        }
+    }
+
+    /// Adds a shape, it's tested linearly against every ray until the next `build_bvh`
     pub fn add_shape(&mut self, s: Shape) {
         self.shapes.push(s);
     }
 
+    /// Builds the bounding volume hierarchy over all shapes
+    pub fn build_bvh(&mut self) {
+        for s in &mut self.shapes {
+            s.build_bvh();
+        }
+        self.build_top_bvh();
+    }
+
+    // Hierarchy over the shapes, their own hierarchies are left alone
+    fn build_top_bvh(&mut self) {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = self.shapes
+            .iter()
+            .map(|s| s.bounding_box())
+            .enumerate()
+            .partition(|&(_, bbox)| bbox.is_some());
+
+        self.bvh = Bvh::build(bounded.into_iter().map(|(i, bbox)| (i, bbox.unwrap())));
+        self.unbounded = unbounded.into_iter().map(|(i, _)| i).collect();
+        self.bvh_len = self.shapes.len();
+    }
+
+    // Indices of the shapes outside of the hierarchy
+    fn linear_shapes(&self) -> Chain<Cloned<slice::Iter<usize>>, Range<usize>> {
+        self.unbounded
+            .iter()
+            .cloned()
+            .chain(self.bvh_len..self.shapes.len())
+    }
+
+    /// Group or transformed shape with the given name
+    pub fn find_node(&self, name: &str) -> Option<&Shape> {
+        self.shapes.iter().filter_map(|s| s.find_node(name)).next()
+    }
+
+    /// Moves the named group or transformed shape and updates the bounding
+    /// volume hierarchies, false if there's no such node
+    pub fn set_transform(&mut self, name: &str, transform: Transform) -> bool {
+        let found = self.shapes
+            .iter_mut()
+            .any(|s| s.set_node_transform(name, &transform));
+        if found {
+            self.build_top_bvh();
+        }
+        found
+    }
+
+    /// Sets the supersampling pattern used by `line_iter`
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampling = sampling;
+    }
+
+    /// Sets the vertical field of view in degrees
+    pub fn set_fov(&mut self, fov: f64) {
+        self.camera_fov = fov;
+    }
+
+    /// Fixes the aspect ratio of the image plane, None derives it from the
+    /// resolution passed to `line_iter`
+    pub fn set_aspect(&mut self, aspect: Option<f64>) {
+        self.camera_aspect = aspect;
+    }
+
+    /// Advances the animated shapes by one frame
     pub fn step(&mut self) {
-        // self.light.translate(&[1.0, 0.0, 0.0]);
+        self.time += STEP_TIME;
+        let time = self.time;
+        let moved = self.shapes
+            .iter_mut()
+            .fold(false, |moved, s| s.animate(time) || moved);
+        if moved {
+            self.build_top_bvh();
+        }
     }
 
     pub fn mv_camera_fwd(&mut self) {
@@ -96,37 +324,85 @@ impl Scene {
             return color::BLACK;
         }
 
-        if let Some(intersect) = self.closest_q(point, dir) {
+        if let Some(mut intersect) = self.closest_q(point, dir) {
+            // Face the normal towards the ray, hits from the inside are exiting the shape
+            let entering = intersect.normal.dot(dir) < 0.0;
+            if !entering {
+                intersect.normal = -intersect.normal;
+            }
+            let material = intersect.material;
+
             let local = self.lights
                 .iter()
                 .map(|l| {
                     let (f_unit, dist) = l.feeler(intersect.point);
-                    let direct_light = self.is_direct_light(intersect.point, f_unit, dist);
-                    if direct_light {
-                        phong(point, &intersect, l)
+                    let transmission = self.light_transmission(intersect.point, f_unit, dist);
+                    if transmission > 0.0 {
+                        phong(point, &intersect, l) * transmission
                     } else {
                         color::BLACK
                     }
                 })
-                .sum::<Color>() + intersect.material.ambient_color;
+                .sum::<Color>() + material.ambient_color;
 
             let tmp = (intersect.point - point).normalize();
             let reflection_dir = tmp - (intersect.normal * 2.0 * tmp.dot(intersect.normal));
 
             let reflected = self.trace(intersect.point, reflection_dir, depth + 1);
 
-            local + (reflected * intersect.material.reflectivity)
+            if material.transparency <= 0.0 {
+                return local + (reflected * material.reflectivity);
+            }
+
+            let (n1, n2) = if entering {
+                (1.0, material.ior)
+            } else {
+                (material.ior, 1.0)
+            };
+
+            // Total internal reflection sends everything to the reflected ray
+            let (kr, refracted) = match refract(tmp, intersect.normal, n1 / n2) {
+                Some(refraction_dir) => {
+                    let cos = if n1 > n2 {
+                        refraction_dir.dot(-intersect.normal)
+                    } else {
+                        -tmp.dot(intersect.normal)
+                    };
+                    let refracted = self.trace(intersect.point, refraction_dir, depth + 1);
+                    (schlick(cos, n1, n2), refracted)
+                }
+                None => (1.0, color::BLACK),
+            };
+
+            let t = material.transparency;
+            local * (1.0 - t) + reflected * (material.reflectivity * (1.0 - t) + kr * t) +
+                refracted * ((1.0 - kr) * t)
         } else {
             color::BLACK
         }
     }
 
-    // Is there anything on the path to the light
-    fn is_direct_light(&self, point: Vec3d, dir: Vec3d, dl: f64) -> bool {
-        !self.shapes
-            .iter()
-            .map(|x| x.intersect_dist(point, dir))
-            .any(|dist_opt| dist_opt.map_or(false, |i| i < dl))
+    // Fraction of the light reaching `point`, 0 behind opaque shapes. A
+    // transparent shape lets its transparency through once, wherever the
+    // ray crosses it
+    fn light_transmission(&self, point: Vec3d, dir: Vec3d, dl: f64) -> f64 {
+        let mut transmission = 1.0;
+        {
+            let mut attenuate = |i: usize| {
+                let shape = &self.shapes[i];
+                if shape.intersect_dist(point, dir).map_or(false, |t| t < dl) {
+                    if let Some(inter) = shape.intersect(point, dir) {
+                        transmission *= inter.material.transparency;
+                    }
+                }
+                transmission > 0.0
+            };
+
+            if self.linear_shapes().all(&mut attenuate) {
+                self.bvh.visit(point, dir, dl, attenuate);
+            }
+        }
+        transmission
     }
 
     // Checks against all objects and returns closest intersection
@@ -139,27 +415,73 @@ impl Scene {
             }
         };
 
-        self.shapes
-            .iter()
-            .map(|x| (x, x.intersect_dist(point, dir)))
-            .fold(None, find_min_opt)
+        let bvh_hit = self.bvh
+            .closest(point, dir, |i| self.shapes[i].intersect_dist(point, dir))
+            .map(|(i, t)| (&self.shapes[i], t));
+
+        self.linear_shapes()
+            .map(|i| (&self.shapes[i], self.shapes[i].intersect_dist(point, dir)))
+            .fold(bvh_hit, find_min_opt)
             .and_then(|(s, _)| s.intersect(point, dir))
     }
 }
 
+// Links the instances inside prototype `name` once the prototypes they use
+// are linked. `path` holds the prototypes being linked, to catch cycles
+fn resolve_prototype(
+    name: &str,
+    pending: &mut HashMap<String, Arc<Shape>>,
+    resolved: &mut HashMap<String, Arc<Shape>>,
+    path: &mut Vec<String>,
+) -> Result<(), CraycrayError> {
+    let mut proto = match pending.remove(name) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    path.push(name.to_string());
+
+    // Nothing instances it yet, so it can still be changed in place
+    if let Some(s) = Arc::get_mut(&mut proto) {
+        while let Err(missing) = s.resolve_prototypes(resolved) {
+            if pending.contains_key(&missing) {
+                resolve_prototype(&missing, pending, resolved, path)?;
+            } else if let Some(start) = path.iter().position(|p| *p == missing) {
+                return Err(CraycrayError::InvalidShape(format!(
+                    "prototypes {} -> {} form a cycle",
+                    path[start..].join(" -> "),
+                    missing
+                )));
+            } else {
+                return Err(CraycrayError::UnknownPrototype(missing));
+            }
+        }
+        s.build_bvh();
+    }
+
+    path.pop();
+    resolved.insert(name.to_string(), proto);
+    Ok(())
+}
+
 /// Iterator that iterates over a single line
 pub struct LineIter<'a> {
     h_res: usize,
     x: usize,
+    line: usize,
     right_step: Vec3d,
+    down_step: Vec3d,
     point: Vec3d,
     scene: &'a Scene,
 }
 
 impl<'a> LineIter<'a> {
     fn new(scene: &Scene, h: usize, v: usize, l: usize) -> LineIter {
-        let left = scene.camera_dir.cross(scene.camera_up).normalize();
-        let up = left.cross(scene.camera_dir).normalize();
+        let half_height = (scene.camera_fov.to_radians() / 2.0).tan();
+        let aspect = scene.camera_aspect.unwrap_or(h as f64 / v as f64);
+        let half_width = half_height * aspect;
+
+        let left = scene.camera_dir.cross(scene.camera_up).normalize() * half_width;
+        let up = left.cross(scene.camera_dir).normalize() * half_height;
         let mut point = left + up + scene.camera_dir;
         let right_step = left * (-2.0 / h as f64);
         let down_step = up * (-2.0 / v as f64);
@@ -168,8 +490,10 @@ impl<'a> LineIter<'a> {
         LineIter {
             h_res: h,
             x: 0,
+            line: l,
             point: point,
             right_step: right_step,
+            down_step: down_step,
             scene: scene,
         }
     }
@@ -186,13 +510,40 @@ impl<'a> Iterator for LineIter<'a> {
 
         self.point += self.right_step;
 
-        let c = self.scene
-            .trace(self.scene.camera_pos, self.point.normalize(), 0);
+        let seed = ((self.line as u64) << 32) | self.x as u64;
+        let offsets = self.scene.sampling.offsets(seed);
+        let n = offsets.len();
+
+        let c = offsets
+            .map(|(dx, dy)| {
+                let dir = self.point + self.right_step * dx + self.down_step * dy;
+                self.scene.trace(self.scene.camera_pos, dir.normalize(), 0)
+            })
+            .sum::<Color>() * (1.0 / n as f64);
 
         Some(c)
     }
 }
 
+// Refracted direction by Snell's law, None on total internal reflection.
+// `normal` faces the incoming ray and `eta` is n1 / n2
+fn refract(dir: Vec3d, normal: Vec3d, eta: f64) -> Option<Vec3d> {
+    let cos_i = -dir.dot(normal);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        None
+    } else {
+        Some((dir * eta + normal * (eta * cos_i - k.sqrt())).normalize())
+    }
+}
+
+// Schlick's approximation of the Fresnel reflectance, `cos` is taken on the
+// side with the lower index of refraction
+fn schlick(cos: f64, n1: f64, n2: f64) -> f64 {
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
 // Calculate color with Phong model
 fn phong(view_point: Vec3d, intersection: &Intersection, light: &Light) -> Color {
     let point_material = intersection.material;
@@ -216,8 +567,130 @@ fn phong(view_point: Vec3d, intersection: &Intersection, light: &Light) -> Color
         s = 0.0;
     }
 
-    let diffuse = light_color * point_material.diffuse_color * d;
+    let diffuse = light_color * intersection.diffuse_color() * d;
     let specular = light_color * point_material.specular_color * s;
 
     diffuse + specular
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use material;
+
+    fn scene() -> Scene {
+        let up = Vec3d::new(0.0, 1.0, 0.0);
+        Scene::new(Vec3d::zero(), Vec3d::new(0.0, 0.0, 1.0), up)
+    }
+
+    #[test]
+    fn empty_scene() {
+        let mut scene = scene();
+        scene.build_bvh();
+        let d = Vec3d::new(0.0, 0.0, 1.0);
+
+        assert!(scene.closest_q(Vec3d::zero(), d).is_none());
+        assert_eq!(scene.light_transmission(Vec3d::zero(), d, 10.0), 1.0);
+    }
+
+    #[test]
+    fn unbounded_only_scene() {
+        let mut scene = scene();
+        let normal = Vec3d::new(0.0, 0.0, -1.0);
+        scene.add_shape(Shape::new_plane(Vec3d::new(0.0, 0.0, 5.0), normal, color::WHITE));
+        scene.build_bvh();
+        assert!(scene.bvh.bbox().is_none());
+
+        let d = Vec3d::new(0.0, 0.0, 1.0);
+        let hit = scene.closest_q(Vec3d::zero(), d).expect("plane hit");
+        assert!((hit.point - Vec3d::new(0.0, 0.0, 5.0)).magnitude() < 1e-9);
+        assert_eq!(scene.light_transmission(Vec3d::zero(), d, 10.0), 0.0);
+        assert_eq!(scene.light_transmission(Vec3d::zero(), d, 4.0), 1.0);
+        assert_eq!(scene.light_transmission(Vec3d::zero(), -d, 10.0), 1.0);
+    }
+
+    #[test]
+    fn transparent_shadows() {
+        let mut scene = scene();
+        let mut glass = material::GLASS;
+        glass.transparency = 0.5;
+        let center = Vec3d::new(0.0, 0.0, 5.0);
+        scene.add_shape(Shape::new_sphere_material(center, 1.0, glass));
+        scene.build_bvh();
+
+        let d = Vec3d::new(0.0, 0.0, 1.0);
+        assert_eq!(scene.light_transmission(Vec3d::zero(), d, 10.0), 0.5);
+
+        let normal = Vec3d::new(0.0, 0.0, -1.0);
+        scene.add_shape(Shape::new_plane(Vec3d::new(0.0, 0.0, 8.0), normal, color::WHITE));
+        assert_eq!(scene.light_transmission(Vec3d::zero(), d, 10.0), 0.0);
+        assert_eq!(scene.light_transmission(Vec3d::zero(), d, 7.0), 0.5);
+    }
+
+    fn with_prototypes(shapes: &str, prototypes: &str) -> Result<Scene, CraycrayError> {
+        let json = format!(
+            r#"{{"shapes": {}, "prototypes": {}, "lights": [], "max_reflection": 1,
+                "camera_pos": {{"x": 0, "y": 0, "z": 0}},
+                "camera_dir": {{"x": 0, "y": 0, "z": 1}},
+                "camera_up": {{"x": 0, "y": 1, "z": 0}}}}"#,
+            shapes,
+            prototypes
+        );
+        let mut scene: Scene = serde_json::from_str(&json).map_err(CraycrayError::Serde)?;
+        scene.resolve_prototypes()?;
+        scene.build_bvh();
+        Ok(scene)
+    }
+
+    fn instance(name: &str, z: f64) -> String {
+        format!(
+            r#"{{"Transformed": {{"shape": {{"Prototype": "{}"}},
+                "transform": [{{"Translate": {{"x": 0, "y": 0, "z": {}}}}}]}}}}"#,
+            name,
+            z
+        )
+    }
+
+    const BALL: &str = r#"{"Sphere": {"center": {"x": 0, "y": 0, "z": 0}, "radius": 1,
+        "material": {"ambient_color": [0, 0, 0], "specular_color": [0, 0, 0],
+                     "diffuse_color": [1, 1, 1], "shininess": 0, "reflectivity": 0}}}"#;
+
+    #[test]
+    fn nested_prototypes() {
+        // The map order decides whether "outer" is visited before "ball"
+        let prototypes = format!(
+            r#"{{"outer": {}, "ball": {}}}"#,
+            instance("ball", 2.0),
+            BALL
+        );
+        let scene = with_prototypes(&format!("[{}]", instance("outer", 3.0)), &prototypes)
+            .unwrap();
+
+        let d = Vec3d::new(0.0, 0.0, 1.0);
+        let hit = scene.closest_q(Vec3d::zero(), d).expect("nested instance hit");
+        assert!((hit.point - Vec3d::new(0.0, 0.0, 4.0)).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn unknown_nested_prototype() {
+        let prototypes = format!(r#"{{"outer": {}}}"#, instance("bal", 2.0));
+        match with_prototypes("[]", &prototypes) {
+            Err(CraycrayError::UnknownPrototype(name)) => assert_eq!(name, "bal"),
+            _ => panic!("misspelled prototype accepted"),
+        }
+    }
+
+    #[test]
+    fn prototype_cycle() {
+        let prototypes = format!(
+            r#"{{"a": {}, "b": {}, "c": {}}}"#,
+            instance("b", 1.0),
+            instance("c", 1.0),
+            instance("a", 1.0)
+        );
+        match with_prototypes("[]", &prototypes) {
+            Err(CraycrayError::InvalidShape(msg)) => assert!(msg.contains("cycle"), "{}", msg),
+            _ => panic!("prototype cycle accepted"),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use cgmath::*;
+use shape::*;
+
+// Most segments per patch and direction
+const MAX_SEGMENTS: usize = 64;
+// Normals shorter than this are taken next to the point, where the patch
+// isn't collapsed
+const MIN_NORMAL: f64 = 1e-12;
+const POLE_OFFSET: f64 = 1e-3;
+
+fn bernstein(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+fn bernstein_derivative(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [-3.0 * s * s, 3.0 * s * (s - 2.0 * t), 3.0 * t * (2.0 * s - t), 3.0 * t * t]
+}
+
+// Segments for a polyline through a cubic curve to stay within `tolerance`,
+// from the bound d(d - 1) / 8 * max |second difference| / n²
+fn curve_segments(c: [Vec3d; 4], tolerance: f64) -> usize {
+    let m = (c[0] - c[1] * 2.0 + c[2])
+        .magnitude()
+        .max((c[1] - c[2] * 2.0 + c[3]).magnitude());
+    let n = (0.75 * m / tolerance).sqrt().ceil() as usize;
+    n.clamp(1, MAX_SEGMENTS)
+}
+
+// Parameter of the segment end closest to `t` on an edge of `segments`
+fn snap(t: f64, segments: usize) -> f64 {
+    (t * segments as f64).round() / segments as f64
+}
+
+/// Bicubic Bézier patch with its 4x4 control points row by row, u runs
+/// along the rows and v across them
+#[derive(Clone, Copy)]
+pub struct BezierPatch {
+    points: [Vec3d; 16],
+}
+
+impl BezierPatch {
+    pub fn new(points: [Vec3d; 16]) -> BezierPatch {
+        BezierPatch { points: points }
+    }
+
+    pub fn points(&self) -> &[Vec3d; 16] {
+        &self.points
+    }
+
+    fn row(&self, i: usize) -> [Vec3d; 4] {
+        let p = &self.points;
+        [p[4 * i], p[4 * i + 1], p[4 * i + 2], p[4 * i + 3]]
+    }
+
+    fn column(&self, j: usize) -> [Vec3d; 4] {
+        let p = &self.points;
+        [p[j], p[4 + j], p[8 + j], p[12 + j]]
+    }
+
+    // Sum of the control points weighted by the basis functions in u and v
+    fn blend(&self, bu: [f64; 4], bv: [f64; 4]) -> Vec3d {
+        (0..16).fold(Vec3d::zero(), |sum, k| sum + self.points[k] * (bv[k / 4] * bu[k % 4]))
+    }
+
+    pub fn point(&self, u: f64, v: f64) -> Vec3d {
+        self.blend(bernstein(u), bernstein(v))
+    }
+
+    /// Unit normal, the cross product of the derivatives in u and v. Collapsed
+    /// edges use the normal right next to them
+    pub fn normal(&self, u: f64, v: f64) -> Vec3d {
+        let n = self.raw_normal(u, v);
+        if n.magnitude2() > MIN_NORMAL {
+            return n.normalize();
+        }
+        let n = self.raw_normal(u + (0.5 - u) * POLE_OFFSET, v + (0.5 - v) * POLE_OFFSET);
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    }
+
+    fn raw_normal(&self, u: f64, v: f64) -> Vec3d {
+        let du = self.blend(bernstein_derivative(u), bernstein(v));
+        let dv = self.blend(bernstein(u), bernstein_derivative(v));
+        du.cross(dv)
+    }
+
+    // Appends a grid of vertices and its triangles. Each edge gets the
+    // segments its own curve needs, so patches that share an edge agree on
+    // it. Grid vertices on coarser edges snap to the nearest segment end,
+    // which leaves some triangles empty but no cracks
+    fn tessellate_into(
+        &self,
+        tolerance: f64,
+        vertices: &mut Vec<Vec3d>,
+        normals: &mut Vec<Vec3d>,
+        indices: &mut Vec<[usize; 3]>,
+    ) {
+        // Half of the tolerance for each direction
+        let tol = tolerance * 0.5;
+        let edges = [
+            curve_segments(self.row(0), tol),
+            curve_segments(self.row(3), tol),
+            curve_segments(self.column(0), tol),
+            curve_segments(self.column(3), tol),
+        ];
+        let nu = (0..4).map(|i| curve_segments(self.row(i), tol)).max().unwrap_or(1);
+        let nv = (0..4).map(|j| curve_segments(self.column(j), tol)).max().unwrap_or(1);
+
+        let base = vertices.len();
+        for i in 0..nv + 1 {
+            let v = i as f64 / nv as f64;
+            for j in 0..nu + 1 {
+                let u = j as f64 / nu as f64;
+                let (u, v) = if i == 0 {
+                    (snap(u, edges[0]), v)
+                } else if i == nv {
+                    (snap(u, edges[1]), v)
+                } else if j == 0 {
+                    (u, snap(v, edges[2]))
+                } else if j == nu {
+                    (u, snap(v, edges[3]))
+                } else {
+                    (u, v)
+                };
+                vertices.push(self.point(u, v));
+                normals.push(self.normal(u, v));
+            }
+        }
+
+        let at = |i: usize, j: usize| base + i * (nu + 1) + j;
+        for i in 0..nv {
+            for j in 0..nu {
+                indices.push([at(i, j), at(i, j + 1), at(i + 1, j + 1)]);
+                indices.push([at(i, j), at(i + 1, j + 1), at(i + 1, j)]);
+            }
+        }
+    }
+}
+
+/// Tessellates the patches into vertices, normals and triangles that stay
+/// within `tolerance` of the surface
+pub fn tessellate(
+    patches: &[BezierPatch],
+    tolerance: f64,
+) -> (Vec<Vec3d>, Vec<Vec3d>, Vec<[usize; 3]>) {
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices = Vec::new();
+    for patch in patches {
+        patch.tessellate_into(tolerance, &mut vertices, &mut normals, &mut indices);
+    }
+    (vertices, normals, indices)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-3;
+
+    // Patch over a 4x4 grid of (x, y) positions with the given heights
+    fn patch(xy: &[(f64, f64)], heights: &[f64]) -> BezierPatch {
+        let mut points = [Vec3d::zero(); 16];
+        for (k, p) in points.iter_mut().enumerate() {
+            *p = Vec3d::new(xy[k].0, xy[k].1, heights[k]);
+        }
+        BezierPatch::new(points)
+    }
+
+    fn unit_grid(y0: f64) -> Vec<(f64, f64)> {
+        (0..16)
+            .map(|k| ((k % 4) as f64 / 3.0, y0 + (k / 4) as f64 / 3.0))
+            .collect()
+    }
+
+    // Grid columns of a tessellated patch, less one
+    fn columns(patch: &BezierPatch) -> usize {
+        (0..4)
+            .map(|i| curve_segments(patch.row(i), TOLERANCE * 0.5))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn flat_patch() {
+        // Unevenly spaced control points on the plane z = 0.5x + 0.25y + 1
+        let xy: Vec<(f64, f64)> = (0..16)
+            .map(|k| {
+                let (i, j) = ((k / 4) as f64, (k % 4) as f64);
+                (j * j + 0.1 * i, i * 1.5 - 0.2 * j * j)
+            })
+            .collect();
+        let heights: Vec<f64> = xy.iter().map(|&(x, y)| 0.5 * x + 0.25 * y + 1.0).collect();
+        let (vertices, normals, indices) = tessellate(&[patch(&xy, &heights)], TOLERANCE);
+
+        assert!(!indices.is_empty());
+        let plane_normal = Vec3d::new(-0.5, -0.25, 1.0).normalize();
+        for (p, n) in vertices.iter().zip(&normals) {
+            assert!((0.5 * p.x + 0.25 * p.y + 1.0 - p.z).abs() < 1e-9, "{:?} off the plane", p);
+            assert!((n - plane_normal).magnitude() < 1e-9, "{:?} != {:?}", n, plane_normal);
+        }
+    }
+
+    #[test]
+    fn shared_edge() {
+        // The edge is a gentle curve, the interiors bend a lot more or not at all
+        let edge = [0.0, 0.1, -0.1, 0.05];
+        let mut heights = vec![0.0, 0.3, -0.2, 0.1, 0.0, 1.0, -1.0, 0.0, 0.2, -0.8, 0.9, 0.0];
+        heights.extend(&edge);
+        let a = patch(&unit_grid(0.0), &heights);
+        let b = patch(&unit_grid(1.0), &edge.iter().cycle().take(16).cloned().collect::<Vec<_>>());
+        assert_ne!(columns(&a), columns(&b));
+
+        // Repeated vertices on coarser edges don't matter
+        let edge_vertices = |vertices: &[Vec3d]| {
+            let mut distinct = vertices.to_vec();
+            distinct.dedup();
+            distinct
+        };
+        let (va, _, _) = tessellate(&[a], TOLERANCE);
+        let (vb, _, _) = tessellate(&[b], TOLERANCE);
+        assert_eq!(
+            edge_vertices(&va[va.len() - columns(&a) - 1..]),
+            edge_vertices(&vb[..columns(&b) + 1])
+        );
+    }
+}
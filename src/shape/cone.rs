@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+
+use cgmath::*;
+use shape::*;
+use color;
+
+const EPSILON: f64 = 1e-6;
+
+/// Cone frustum between two points with a radius at each end,
+/// a zero radius gives a pointed cone
+#[derive(Serialize, Deserialize)]
+pub struct Cone {
+    material: Material,
+    start: Vec3d,
+    end: Vec3d,
+    start_radius: f64,
+    end_radius: f64,
+    #[serde(default = "default_capped")]
+    capped: bool,
+}
+
+pub fn default_capped() -> bool {
+    true
+}
+
+impl Cone {
+    pub fn new(start: Vec3d, end: Vec3d, start_radius: f64, end_radius: f64, c: Color) -> Cone {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Cone::from_material(start, end, start_radius, end_radius, material)
+    }
+
+    pub fn from_material(
+        start: Vec3d,
+        end: Vec3d,
+        start_radius: f64,
+        end_radius: f64,
+        m: Material,
+    ) -> Cone {
+        Cone {
+            material: m,
+            start: start,
+            end: end,
+            start_radius: start_radius,
+            end_radius: end_radius,
+            capped: true,
+        }
+    }
+
+    /// Leaves the ends open
+    pub fn uncapped(mut self) -> Cone {
+        self.capped = false;
+        self
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn frustum(&self) -> Frustum {
+        Frustum {
+            start: self.start,
+            end: self.end,
+            start_radius: self.start_radius,
+            end_radius: self.end_radius,
+            capped: self.capped,
+        }
+    }
+}
+
+/// Surface whose radius changes linearly along the axis, shared by cones and cylinders
+pub struct Frustum {
+    pub start: Vec3d,
+    pub end: Vec3d,
+    pub start_radius: f64,
+    pub end_radius: f64,
+    pub capped: bool,
+}
+
+impl Frustum {
+    /// Closest hit distance and outward normal
+    pub fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, Vec3d)> {
+        self.line_hits(p0, d).into_iter().find(|&(t, _)| t > EPSILON)
+    }
+
+    /// Every crossing of the line with the surface, sorted by distance
+    pub fn line_hits(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Vec3d)> {
+        let mut hits = self.side_hits(p0, d);
+        if self.capped {
+            let a = self.axis();
+            hits.extend(cap_hit(p0, d, self.start, -a, self.start_radius));
+            hits.extend(cap_hit(p0, d, self.end, a, self.end_radius));
+        }
+        hits.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(Ordering::Equal));
+        hits
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let a = self.axis();
+        // Half extents of a disk perpendicular to `a` with unit radius
+        let disk = Vec3d::new(
+            (1.0 - a.x * a.x).max(0.0).sqrt(),
+            (1.0 - a.y * a.y).max(0.0).sqrt(),
+            (1.0 - a.z * a.z).max(0.0).sqrt(),
+        );
+        let start_ext = disk * self.start_radius;
+        let end_ext = disk * self.end_radius;
+        Aabb::from_points(vec![
+            self.start - start_ext,
+            self.start + start_ext,
+            self.end - end_ext,
+            self.end + end_ext,
+        ])
+    }
+
+    fn axis(&self) -> Vec3d {
+        (self.end - self.start).normalize()
+    }
+
+    fn side_hits(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Vec3d)> {
+        let a = self.axis();
+        let height = (self.end - self.start).magnitude();
+        // Radius growth per unit along the axis
+        let k = (self.end_radius - self.start_radius) / height;
+
+        let w = p0 - self.start;
+        let w_perp = w - a * w.dot(a);
+        let d_perp = d - a * d.dot(a);
+        // Radius along the ray is r_a + t * r_b
+        let r_a = self.start_radius + k * w.dot(a);
+        let r_b = k * d.dot(a);
+
+        let qa = d_perp.dot(d_perp) - r_b * r_b;
+        let qb = 2.0 * (d_perp.dot(w_perp) - r_a * r_b);
+        let qc = w_perp.dot(w_perp) - r_a * r_a;
+
+        let roots = if qa.abs() < EPSILON {
+            // Ray parallel to the slant, it crosses the side once at most
+            if qb.abs() < EPSILON {
+                return Vec::new();
+            }
+            vec![-qc / qb]
+        } else {
+            let delta = qb * qb - 4.0 * qa * qc;
+            if delta < 0.0 {
+                return Vec::new();
+            }
+            let delta_sq = delta.sqrt();
+            vec![(-qb - delta_sq) / (2.0 * qa), (-qb + delta_sq) / (2.0 * qa)]
+        };
+
+        roots
+            .into_iter()
+            .filter_map(|t| {
+                let h = w.dot(a) + t * d.dot(a);
+                if h < 0.0 || h > height {
+                    return None;
+                }
+                let q_perp = w_perp + d_perp * t;
+                let r = self.start_radius + k * h;
+                Some((t, (q_perp - a * (r * k)).normalize()))
+            })
+            .collect()
+    }
+}
+
+// Disk at `center` facing `normal`
+fn cap_hit(
+    p0: Vec3d,
+    d: Vec3d,
+    center: Vec3d,
+    normal: Vec3d,
+    radius: f64,
+) -> Option<(f64, Vec3d)> {
+    let denom = normal.dot(d);
+    if denom.abs() < EPSILON || radius <= 0.0 {
+        return None;
+    }
+    let t = (center - p0).dot(normal) / denom;
+    let q = p0 + d * t;
+    if (q - center).magnitude2() <= radius * radius {
+        Some((t, normal))
+    } else {
+        None
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.frustum().hit(p0, d).map(|(t, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.frustum().hit(p0, d).map(|(t, n)| Intersection::new(&self.material, p0 + d * t, n))
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.frustum()
+            .line_hits(p0, d)
+            .into_iter()
+            .map(|(t, n)| (t, Intersection::new(&self.material, p0 + d * t, n)))
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.frustum().bounding_box())
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::*;
+use shape::*;
+
+// Minimum hit distance, keeps rays leaving the surface from hitting it again
+const EPSILON: f64 = 1e-6;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Whether the shape is entered at a crossing, normals facing the ray enter
+// and normals facing away leave. Grazing crossings flip the state
+fn enters(inter: &Intersection, d: Vec3d, inside: bool) -> bool {
+    let facing = inter.normal.dot(d);
+    if facing < 0.0 {
+        true
+    } else if facing > 0.0 {
+        false
+    } else {
+        !inside
+    }
+}
+
+/// Combines the sorted crossings of two shapes along the direction `d`. Walks
+/// both lists in order and keeps the crossings where the combined inside
+/// state changes. The state comes from the normals rather than from counting
+/// crossings, so open surfaces like disks only bound the side they face
+pub fn merge_crossings<'a>(
+    op: CsgOp,
+    d: Vec3d,
+    left: Vec<(f64, Intersection<'a>)>,
+    right: Vec<(f64, Intersection<'a>)>,
+) -> Vec<(f64, Intersection<'a>)> {
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    let mut in_left = false;
+    let mut in_right = false;
+    let mut crossings = Vec::new();
+
+    loop {
+        let from_left = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => l.0 <= r.0,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+
+        let was_inside = op.inside(in_left, in_right);
+        let (t, mut inter) = if from_left {
+            let crossing = left.next().unwrap();
+            in_left = enters(&crossing.1, d, in_left);
+            crossing
+        } else {
+            let crossing = right.next().unwrap();
+            in_right = enters(&crossing.1, d, in_right);
+            crossing
+        };
+
+        if op.inside(in_left, in_right) != was_inside {
+            // The subtracted solid's surface faces into the result
+            if !from_left && op == CsgOp::Difference {
+                inter.normal = -inter.normal;
+            }
+            crossings.push((t, inter));
+        }
+    }
+    crossings
+}
+
+/// Boolean combination of two solids
+#[derive(Serialize, Deserialize)]
+pub struct Csg {
+    op: CsgOp,
+    left: Box<Shape>,
+    right: Box<Shape>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Shape, right: Shape) -> Csg {
+        Csg {
+            op: op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    pub fn map_materials<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&mut Material),
+    {
+        self.left.map_materials(f);
+        self.right.map_materials(f);
+    }
+
+    pub fn build_bvh(&mut self) {
+        self.left.build_bvh();
+        self.right.build_bvh();
+    }
+
+    pub fn resolve_prototypes(
+        &mut self,
+        prototypes: &HashMap<String, Arc<Shape>>,
+    ) -> Result<(), String> {
+        self.left.resolve_prototypes(prototypes)?;
+        self.right.resolve_prototypes(prototypes)
+    }
+
+    pub fn operands(&self) -> (&Shape, &Shape) {
+        (&self.left, &self.right)
+    }
+
+    pub fn operands_mut(&mut self) -> (&mut Shape, &mut Shape) {
+        (&mut self.left, &mut self.right)
+    }
+
+    fn first_hit(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, Intersection)> {
+        self.crossings(p0, d).into_iter().find(|&(t, _)| t > EPSILON)
+    }
+}
+
+impl Intersectable for Csg {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.first_hit(p0, d).map(|(t, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.first_hit(p0, d).map(|(_, inter)| inter)
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        merge_crossings(self.op, d, self.left.crossings(p0, d), self.right.crossings(p0, d))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.op {
+            CsgOp::Union => match (left, right) {
+                (Some(l), Some(r)) => Some(l.union(r)),
+                _ => None,
+            },
+            CsgOp::Intersection => match (left, right) {
+                (Some(l), Some(r)) => Some(l.intersection(r)),
+                (Some(b), None) | (None, Some(b)) => Some(b),
+                (None, None) => None,
+            },
+            CsgOp::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color;
+
+    fn material() -> Material {
+        Material {
+            ambient_color: color::BLACK,
+            specular_color: color::BLACK,
+            diffuse_color: color::WHITE,
+            shininess: 0.0,
+            reflectivity: 0.0,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        }
+    }
+
+    // Crossings of a solid that spans `intervals` along the x axis
+    fn solid<'a>(m: &'a Material, intervals: &[(f64, f64)]) -> Vec<(f64, Intersection<'a>)> {
+        let x = Vec3d::new(1.0, 0.0, 0.0);
+        intervals
+            .iter()
+            .flat_map(|&(t0, t1)| {
+                vec![
+                    (t0, Intersection::new(m, x * t0, -x)),
+                    (t1, Intersection::new(m, x * t1, x)),
+                ]
+            })
+            .collect()
+    }
+
+    fn merged(op: CsgOp, left: &[(f64, f64)], right: &[(f64, f64)]) -> Vec<(f64, f64)> {
+        let m = material();
+        let x = Vec3d::new(1.0, 0.0, 0.0);
+        merge_crossings(op, x, solid(&m, left), solid(&m, right))
+            .into_iter()
+            .map(|(t, inter)| (t, inter.normal.x))
+            .collect()
+    }
+
+    #[test]
+    fn union() {
+        let overlapping = merged(CsgOp::Union, &[(1.0, 3.0)], &[(2.0, 4.0)]);
+        assert_eq!(overlapping, vec![(1.0, -1.0), (4.0, 1.0)]);
+        let apart = merged(CsgOp::Union, &[(1.0, 2.0)], &[(3.0, 4.0)]);
+        assert_eq!(apart, vec![(1.0, -1.0), (2.0, 1.0), (3.0, -1.0), (4.0, 1.0)]);
+    }
+
+    #[test]
+    fn intersection() {
+        let overlapping = merged(CsgOp::Intersection, &[(1.0, 3.0)], &[(2.0, 4.0)]);
+        assert_eq!(overlapping, vec![(2.0, -1.0), (3.0, 1.0)]);
+        assert!(merged(CsgOp::Intersection, &[(1.0, 2.0)], &[(3.0, 4.0)]).is_empty());
+    }
+
+    #[test]
+    fn difference() {
+        // The subtracted solid's surface faces into the result
+        let overlapping = merged(CsgOp::Difference, &[(1.0, 3.0)], &[(2.0, 4.0)]);
+        assert_eq!(overlapping, vec![(1.0, -1.0), (2.0, 1.0)]);
+        let hole = merged(CsgOp::Difference, &[(1.0, 4.0)], &[(2.0, 3.0)]);
+        assert_eq!(hole, vec![(1.0, -1.0), (2.0, 1.0), (3.0, -1.0), (4.0, 1.0)]);
+        assert!(merged(CsgOp::Difference, &[(2.0, 3.0)], &[(1.0, 4.0)]).is_empty());
+    }
+
+    #[test]
+    fn open_operand() {
+        // A disk the ray leaves through doesn't put it inside
+        let m = material();
+        let x = Vec3d::new(1.0, 0.0, 0.0);
+        let disk = vec![(2.0, Intersection::new(&m, x * 2.0, x))];
+        let union: Vec<f64> = merge_crossings(CsgOp::Union, x, solid(&m, &[(1.0, 4.0)]), disk)
+            .into_iter()
+            .map(|c| c.0)
+            .collect();
+        assert_eq!(union, vec![1.0, 4.0]);
+
+        let disk = vec![(2.0, Intersection::new(&m, x * 2.0, -x))];
+        let cut: Vec<f64> = merge_crossings(CsgOp::Difference, x, solid(&m, &[(1.0, 4.0)]), disk)
+            .into_iter()
+            .map(|c| c.0)
+            .collect();
+        assert_eq!(cut, vec![1.0, 2.0]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::f64;
+
+use cgmath::*;
+use shape::*;
+use vec3d::Rotatable;
+use color;
+
+const EPSILON: f64 = 1e-6;
+
+/// Box given by its min/max corners, optionally rotated around its center
+#[derive(Serialize, Deserialize)]
+pub struct Cuboid {
+    material: Material,
+    min: Vec3d,
+    max: Vec3d,
+    /// Rotation around the x, y and z axes in radians, applied in that order
+    #[serde(default)]
+    rotation: Option<Vec3d>,
+}
+
+impl Cuboid {
+    pub fn new(min: Vec3d, max: Vec3d, c: Color) -> Cuboid {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Cuboid::from_material(min, max, material)
+    }
+
+    pub fn from_material(min: Vec3d, max: Vec3d, m: Material) -> Cuboid {
+        Cuboid {
+            material: m,
+            min: min,
+            max: max,
+            rotation: None,
+        }
+    }
+
+    /// Rotates the box around its center, angles in radians
+    pub fn rotated(mut self, rotation: Vec3d) -> Cuboid {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn center(&self) -> Vec3d {
+        (self.min + self.max) * 0.5
+    }
+
+    // World to box space, only the rotation part
+    fn to_local(&self, v: Vec3d) -> Vec3d {
+        match self.rotation {
+            Some(r) => v.rot_z(-r.z).rot_y(-r.y).rot_x(-r.x),
+            None => v,
+        }
+    }
+
+    fn to_world(&self, v: Vec3d) -> Vec3d {
+        match self.rotation {
+            Some(r) => v.rot_x(r.x).rot_y(r.y).rot_z(r.z),
+            None => v,
+        }
+    }
+
+    // Closest hit, returns the distance and the outward local normal
+    fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, Vec3d)> {
+        self.slabs(p0, d).and_then(|(near, far)| if near.0 > EPSILON {
+            Some(near)
+        } else if far.0 > EPSILON {
+            Some(far)
+        } else {
+            None
+        })
+    }
+
+    // Slab test in box space, returns where the line enters and leaves the
+    // box together with the outward local normals
+    fn slabs(&self, p0: Vec3d, d: Vec3d) -> Option<((f64, Vec3d), (f64, Vec3d))> {
+        let half = (self.max - self.min) * 0.5;
+        let p = self.to_local(p0 - self.center());
+        let d = self.to_local(d);
+
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+        let mut near_axis = 0;
+        let mut far_axis = 0;
+
+        for axis in 0..3 {
+            if d[axis].abs() < EPSILON {
+                // Parallel to the slab, has to start between the planes
+                if p[axis].abs() > half[axis] {
+                    return None;
+                }
+                continue;
+            }
+
+            let t0 = (-half[axis] - p[axis]) / d[axis];
+            let t1 = (half[axis] - p[axis]) / d[axis];
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if t0 > t_near {
+                t_near = t0;
+                near_axis = axis;
+            }
+            if t1 < t_far {
+                t_far = t1;
+                far_axis = axis;
+            }
+            if t_near > t_far {
+                return None;
+            }
+        }
+
+        // Entering faces look against the ray, exiting faces along it
+        let face_normal = |axis: usize, sign: f64| {
+            let mut n = Vec3d::zero();
+            n[axis] = sign;
+            n
+        };
+
+        Some((
+            (t_near, face_normal(near_axis, -d[near_axis].signum())),
+            (t_far, face_normal(far_axis, d[far_axis].signum())),
+        ))
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.hit(p0, d).map(|(t, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.hit(p0, d).map(|(t, n)| {
+            Intersection::new(&self.material, p0 + d * t, self.to_world(n))
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        match self.slabs(p0, d) {
+            Some((near, far)) => vec![near, far]
+                .into_iter()
+                .map(|(t, n)| (t, Intersection::new(&self.material, p0 + d * t, self.to_world(n))))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let center = self.center();
+        let half = (self.max - self.min) * 0.5;
+        let corners = (0..8).map(|i| {
+            let corner = Vec3d::new(
+                if i & 1 == 0 { -half.x } else { half.x },
+                if i & 2 == 0 { -half.y } else { half.y },
+                if i & 4 == 0 { -half.z } else { half.z },
+            );
+            center + self.to_world(corner)
+        });
+        Some(Aabb::from_points(corners))
+    }
+}
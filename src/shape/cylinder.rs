@@ -0,0 +1,82 @@
+use shape::*;
+use shape::cone::{self, Frustum};
+use color;
+
+/// Cylinder between two points
+#[derive(Serialize, Deserialize)]
+pub struct Cylinder {
+    material: Material,
+    start: Vec3d,
+    end: Vec3d,
+    radius: f64,
+    #[serde(default = "cone::default_capped")]
+    capped: bool,
+}
+
+impl Cylinder {
+    pub fn new(start: Vec3d, end: Vec3d, radius: f64, c: Color) -> Cylinder {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Cylinder::from_material(start, end, radius, material)
+    }
+
+    pub fn from_material(start: Vec3d, end: Vec3d, radius: f64, m: Material) -> Cylinder {
+        Cylinder {
+            material: m,
+            start: start,
+            end: end,
+            radius: radius,
+            capped: true,
+        }
+    }
+
+    /// Leaves the ends open, e.g. for pipes
+    pub fn uncapped(mut self) -> Cylinder {
+        self.capped = false;
+        self
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn frustum(&self) -> Frustum {
+        Frustum {
+            start: self.start,
+            end: self.end,
+            start_radius: self.radius,
+            end_radius: self.radius,
+            capped: self.capped,
+        }
+    }
+}
+
+impl Intersectable for Cylinder {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.frustum().hit(p0, d).map(|(t, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.frustum().hit(p0, d).map(|(t, n)| Intersection::new(&self.material, p0 + d * t, n))
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.frustum()
+            .line_hits(p0, d)
+            .into_iter()
+            .map(|(t, n)| (t, Intersection::new(&self.material, p0 + d * t, n)))
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.frustum().bounding_box())
+    }
+}
@@ -0,0 +1,107 @@
+use cgmath::*;
+use shape::*;
+use vec3d::orthonormal_basis;
+use color;
+
+const EPSILON: f64 = 1e-6;
+
+/// Flat, two sided disk
+#[derive(Serialize, Deserialize)]
+pub struct Disk {
+    material: Material,
+    center: Vec3d,
+    normal: Vec3d,
+    radius: f64,
+}
+
+impl Disk {
+    pub fn new(center: Vec3d, normal: Vec3d, radius: f64, c: Color) -> Disk {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::BLACK,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Disk::from_material(center, normal, radius, material)
+    }
+
+    pub fn from_material(center: Vec3d, normal: Vec3d, radius: f64, m: Material) -> Disk {
+        Disk {
+            material: m,
+            center: center,
+            normal: normal.normalize(),
+            radius: radius,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.line_hit(p0, d).and_then(|t| if t > EPSILON { Some(t) } else { None })
+    }
+
+    // Crossing of the whole line, behind the origin included
+    fn line_hit(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        let denom = self.normal.dot(d);
+        if denom.abs() < EPSILON {
+            return None;
+        }
+
+        let t = (self.center - p0).dot(self.normal) / denom;
+        let q = p0 + d * t;
+        if (q - self.center).magnitude2() <= self.radius * self.radius {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    /// Surface coordinates of a point on the disk, the disk's bounding
+    /// square maps to [0, 1]²
+    pub fn uv(&self, point: Vec3d) -> (f64, f64) {
+        let (u_axis, v_axis) = orthonormal_basis(self.normal);
+        let rel = (point - self.center) / (2.0 * self.radius);
+        (0.5 + rel.dot(u_axis), 0.5 + rel.dot(v_axis))
+    }
+}
+
+impl Intersectable for Disk {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.hit(p0, d)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.hit(p0, d).map(|t| {
+            let q = p0 + d * t;
+            let (u, v) = self.uv(q);
+            Intersection::new(&self.material, q, self.normal).with_uv(u, v)
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.line_hit(p0, d)
+            .map(|t| {
+                let q = p0 + d * t;
+                let (u, v) = self.uv(q);
+                (t, Intersection::new(&self.material, q, self.normal).with_uv(u, v))
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let n = self.normal;
+        let ext = Vec3d::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt(),
+        ) * self.radius;
+        Some(Aabb::new(self.center - ext, self.center + ext))
+    }
+}
@@ -0,0 +1,284 @@
+use cgmath::*;
+use shape::*;
+use shape::sdf::{gradient_normal, sphere_trace, MAX_DISTANCE};
+use color;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum FractalKind {
+    /// Power 8 by default, bailout 2
+    Mandelbulb,
+    /// Sponge filling [-1, 1]³, power is the subdivision factor (3) and the
+    /// bailout is ignored
+    Menger,
+    /// Quaternion Julia set of z^power + c, power 2 by default, bailout 4
+    Julia { c: [f64; 4] },
+}
+
+impl FractalKind {
+    fn default_power(&self) -> f64 {
+        match *self {
+            FractalKind::Mandelbulb => 8.0,
+            FractalKind::Menger => 3.0,
+            FractalKind::Julia { .. } => 2.0,
+        }
+    }
+
+    fn default_bailout(&self) -> f64 {
+        match *self {
+            FractalKind::Mandelbulb => 2.0,
+            FractalKind::Menger => 0.0,
+            FractalKind::Julia { .. } => 4.0,
+        }
+    }
+}
+
+/// Fractal surface rendered by marching its distance estimate. Hits carry
+/// an orbit trap for the material's `trap_color`
+#[derive(Serialize, Deserialize)]
+pub struct Fractal {
+    material: Material,
+    kind: FractalKind,
+    center: Vec3d,
+    /// Size of the unit fractal in the scene
+    #[serde(default = "default_scale")]
+    scale: f64,
+    #[serde(default = "default_iterations")]
+    iterations: usize,
+    /// None picks the kind's default
+    #[serde(default)]
+    power: Option<f64>,
+    /// None picks the kind's default, the Menger sponge has none
+    #[serde(default)]
+    bailout: Option<f64>,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_iterations() -> usize {
+    8
+}
+
+impl Fractal {
+    pub fn new(kind: FractalKind, center: Vec3d, scale: f64, c: Color) -> Fractal {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Fractal::from_material(kind, center, scale, material)
+    }
+
+    pub fn from_material(kind: FractalKind, center: Vec3d, scale: f64, m: Material) -> Fractal {
+        Fractal {
+            material: m,
+            kind: kind,
+            center: center,
+            scale: scale,
+            iterations: default_iterations(),
+            power: None,
+            bailout: None,
+        }
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Fractal {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn with_power(mut self, power: f64) -> Fractal {
+        self.power = Some(power);
+        self
+    }
+
+    pub fn with_bailout(mut self, bailout: f64) -> Fractal {
+        self.bailout = Some(bailout);
+        self
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    /// Distance estimate and orbit trap at a point in the scene
+    pub fn estimate(&self, p: Vec3d) -> (f64, f64) {
+        let q = (p - self.center) / self.scale;
+        let power = self.power.unwrap_or_else(|| self.kind.default_power());
+        let bailout = self.bailout();
+        let (dist, trap) = match self.kind {
+            FractalKind::Mandelbulb => mandelbulb(q, self.iterations, power, bailout),
+            FractalKind::Menger => menger(q, self.iterations, power),
+            FractalKind::Julia { c } => {
+                let c = Quaternion::new(c[0], c[1], c[2], c[3]);
+                julia(q, c, self.iterations, power, bailout)
+            }
+        };
+        (dist * self.scale, trap.clamp(0.0, 1.0))
+    }
+
+    fn bailout(&self) -> f64 {
+        self.bailout.unwrap_or_else(|| self.kind.default_bailout())
+    }
+
+    // Radius of a sphere around the unit sized fractal. Orbits that start
+    // past the bailout end right away, and Julia sets stay within 1 + |c|
+    fn radius(&self) -> f64 {
+        match self.kind {
+            FractalKind::Mandelbulb => self.bailout(),
+            FractalKind::Menger => 3.0f64.sqrt(),
+            FractalKind::Julia { c } => {
+                let c = Quaternion::new(c[0], c[1], c[2], c[3]);
+                self.bailout().max(1.0 + c.magnitude())
+            }
+        }
+    }
+
+    fn distance(&self, p: Vec3d) -> f64 {
+        self.estimate(p).0
+    }
+
+    // Line range inside the bounding sphere
+    fn range(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, f64)> {
+        let radius = self.radius() * self.scale;
+        let oc = p0 - self.center;
+        let b = oc.dot(d);
+        let delta = b * b - (oc.dot(oc) - radius * radius);
+        if delta < 0.0 {
+            return None;
+        }
+        let delta_sq = delta.sqrt();
+        Some((-b - delta_sq, (-b + delta_sq).min(MAX_DISTANCE)))
+    }
+
+    fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.range(p0, d).and_then(|(t0, t1)| {
+            let from_surface = t0 <= 0.0;
+            sphere_trace(|p| self.distance(p), p0, d, t0.max(0.0), t1, from_surface, true)
+                .into_iter()
+                .next()
+        })
+    }
+
+    fn intersection(&self, q: Vec3d) -> Intersection {
+        let normal = gradient_normal(|p| self.distance(p), q);
+        let (_, trap) = self.estimate(q);
+        Intersection::new(&self.material, q, normal).with_trap(trap)
+    }
+}
+
+// Distance estimate of the power n Mandelbulb, trap is the closest orbit
+// point to the origin
+fn mandelbulb(p: Vec3d, iterations: usize, power: f64, bailout: f64) -> (f64, f64) {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.magnitude();
+    let mut trap = r;
+
+    for _ in 0..iterations {
+        if r > bailout || r == 0.0 {
+            break;
+        }
+
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z = Vec3d::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * zr + p;
+
+        r = z.magnitude();
+        trap = trap.min(r);
+    }
+
+    if r == 0.0 {
+        return (0.0, 0.0);
+    }
+    (0.5 * r.ln() * r / dr, trap)
+}
+
+// Menger sponge filling [-1, 1]³, every iteration cuts the crosses out of
+// a `power` times finer grid. Trap is the closest fold to a cell center
+fn menger(p: Vec3d, iterations: usize, power: f64) -> (f64, f64) {
+    let q = Vec3d::new(p.x.abs(), p.y.abs(), p.z.abs()) - Vec3d::new(1.0, 1.0, 1.0);
+    let outside = Vec3d::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+    let mut dist = outside + q.x.max(q.y).max(q.z).min(0.0);
+    let mut trap = 1.0f64;
+
+    let mut s = 1.0;
+    for _ in 0..iterations {
+        let wrap = |x: f64| {
+            let m = x * s;
+            m - 2.0 * (m / 2.0).floor() - 1.0
+        };
+        let a = Vec3d::new(wrap(p.x), wrap(p.y), wrap(p.z));
+        s *= power;
+        let r = Vec3d::new(
+            (1.0 - power * a.x.abs()).abs(),
+            (1.0 - power * a.y.abs()).abs(),
+            (1.0 - power * a.z.abs()).abs(),
+        );
+        let da = r.x.max(r.y);
+        let db = r.y.max(r.z);
+        let dc = r.z.max(r.x);
+        let c = (da.min(db).min(dc) - 1.0) / s;
+        dist = dist.max(c);
+        trap = trap.min(a.magnitude() / 3.0f64.sqrt());
+    }
+
+    (dist, trap)
+}
+
+// Quaternion Julia set of z^n + c, with the scalar derivative estimate
+fn julia(p: Vec3d, c: Quaternion<f64>, iterations: usize, power: f64, bailout: f64) -> (f64, f64) {
+    let n = power.round().max(2.0) as i32;
+    let mut z = Quaternion::new(p.x, p.y, p.z, 0.0);
+    let mut dz = 1.0;
+    let mut r = z.magnitude();
+    let mut trap = r;
+
+    for _ in 0..iterations {
+        if r > bailout {
+            break;
+        }
+        dz *= f64::from(n) * r.powi(n - 1);
+        z = (1..n).fold(z, |acc, _| acc * z) + c;
+        r = z.magnitude();
+        trap = trap.min(r);
+    }
+
+    if r == 0.0 || dz == 0.0 {
+        return (0.0, 0.0);
+    }
+    (0.5 * r * r.ln() / dz, trap)
+}
+
+impl Intersectable for Fractal {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.hit(p0, d)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.hit(p0, d).map(|t| self.intersection(p0 + d * t))
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        match self.range(p0, d) {
+            Some((t0, t1)) => sphere_trace(|p| self.distance(p), p0, d, t0, t1, false, false)
+                .into_iter()
+                .map(|t| (t, self.intersection(p0 + d * t)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius() * self.scale;
+        let ext = Vec3d::new(r, r, r);
+        Some(Aabb::new(self.center - ext, self.center + ext))
+    }
+}
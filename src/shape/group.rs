@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use shape::*;
+use shape::csg::{merge_crossings, CsgOp};
+use shape::transformed::hit_to_world;
+use bvh::Bvh;
+use transform::Transform;
+use scene::CraycrayError;
+
+/// Scene graph node, moves its children as one unit
+#[derive(Serialize, Deserialize)]
+pub struct Group {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    transform: Transform,
+    children: Vec<Shape>,
+    #[serde(skip)]
+    bvh: Bvh,
+    // Children without bounds, tested against every ray
+    #[serde(skip)]
+    unbounded: Vec<usize>,
+}
+
+impl Group {
+    pub fn new(name: Option<String>, transform: Transform, children: Vec<Shape>) -> Group {
+        let mut group = Group {
+            name: name,
+            transform: transform,
+            children: children,
+            bvh: Bvh::default(),
+            unbounded: Vec::new(),
+        };
+        group.build_bvh();
+        group
+    }
+
+    /// Names the node so it can be found with `Scene::find_node`
+    pub fn named(mut self, name: &str) -> Group {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    pub fn children(&self) -> &[Shape] {
+        &self.children
+    }
+
+    /// Builds the hierarchies of the children and the one over them
+    pub fn build_bvh(&mut self) {
+        for c in &mut self.children {
+            c.build_bvh();
+        }
+        self.build_children_bvh();
+    }
+
+    /// Rebuilds only the hierarchy over the children, after one of them moved
+    pub fn build_children_bvh(&mut self) {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = self.children
+            .iter()
+            .map(|s| s.bounding_box())
+            .enumerate()
+            .partition(|&(_, bbox)| bbox.is_some());
+
+        self.bvh = Bvh::build(bounded.into_iter().map(|(i, bbox)| (i, bbox.unwrap())));
+        self.unbounded = unbounded.into_iter().map(|(i, _)| i).collect();
+    }
+
+    pub fn map_materials<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&mut Material),
+    {
+        for c in &mut self.children {
+            c.map_materials(f);
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.children.iter().any(|c| c.is_animated())
+    }
+
+    pub fn animate(&mut self, time: f64) -> bool {
+        let moved = self.children
+            .iter_mut()
+            .fold(false, |moved, c| c.animate(time) || moved);
+        if moved {
+            self.build_children_bvh();
+        }
+        moved
+    }
+
+    pub fn load_resources(&mut self, base_dir: &Path) -> Result<(), CraycrayError> {
+        for c in &mut self.children {
+            c.load_resources(base_dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn resolve_prototypes(
+        &mut self,
+        prototypes: &HashMap<String, Arc<Shape>>,
+    ) -> Result<(), String> {
+        for c in &mut self.children {
+            c.resolve_prototypes(prototypes)?;
+        }
+        Ok(())
+    }
+
+    /// Depth first search for a node by name
+    pub fn find_node(&self, name: &str) -> Option<&Shape> {
+        self.children.iter().filter_map(|c| c.find_node(name)).next()
+    }
+
+    /// Sets the transform of a named descendant, true if it was found
+    pub fn set_node_transform(&mut self, name: &str, transform: &Transform) -> bool {
+        let found = self.children
+            .iter_mut()
+            .any(|c| c.set_node_transform(name, transform));
+        if found {
+            self.build_children_bvh();
+        }
+        found
+    }
+
+    // Closest child along an object space ray and its distance
+    fn closest(&self, p0: Vec3d, d: Vec3d) -> Option<(&Shape, f64)> {
+        let bvh_hit = self.bvh
+            .closest(p0, d, |i| self.children[i].intersect_dist(p0, d))
+            .map(|(i, t)| (&self.children[i], t));
+
+        self.unbounded
+            .iter()
+            .filter_map(|&i| {
+                let c = &self.children[i];
+                c.intersect_dist(p0, d).map(|t| (c, t))
+            })
+            .fold(bvh_hit, |min, hit| match min {
+                Some((_, min_t)) if min_t <= hit.1 => min,
+                _ => Some(hit),
+            })
+    }
+}
+
+impl Intersectable for Group {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        let (local_p0, local_d, scale) = self.transform.ray_to_local(p0, d);
+        self.closest(local_p0, local_d).map(|(_, t)| t * scale)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        let (local_p0, local_d, scale) = self.transform.ray_to_local(p0, d);
+        self.closest(local_p0, local_d).and_then(|(s, t)| {
+            s.intersect(local_p0, local_d)
+                .map(|inter| hit_to_world(&self.transform, inter, p0, d, t * scale))
+        })
+    }
+
+    // The children are combined as a union
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        let (local_p0, local_d, scale) = self.transform.ray_to_local(p0, d);
+        self.children
+            .iter()
+            .map(|c| c.crossings(local_p0, local_d))
+            .fold(Vec::new(), |acc, c| merge_crossings(CsgOp::Union, local_d, acc, c))
+            .into_iter()
+            .map(|(t, inter)| {
+                let t = t * scale;
+                (t, hit_to_world(&self.transform, inter, p0, d, t))
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.bvh.bbox().map(|b| self.transform.bbox(b))
+    }
+}
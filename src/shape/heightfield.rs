@@ -0,0 +1,255 @@
+use std::f64;
+use std::path::Path;
+
+use cgmath::*;
+use shape::*;
+use shape::triangle::line_triangle_uv;
+use loader::heightmap::load_heightmap;
+use scene::CraycrayError;
+
+const EPSILON: f64 = 1e-6;
+
+/// Terrain from a grayscale image, every pixel is a grid vertex. Rays walk
+/// the grid cell by cell and test the two triangles of each cell
+#[derive(Serialize, Deserialize)]
+pub struct Heightfield {
+    material: Material,
+    /// PNG or PGM image, relative to the scene file
+    path: String,
+    /// Corner of the terrain at height 0, the image spans x and z
+    origin: Vec3d,
+    /// Extent along x and z, y is the height of a white pixel
+    size: Vec3d,
+    #[serde(skip)]
+    nx: usize,
+    #[serde(skip)]
+    nz: usize,
+    #[serde(skip)]
+    heights: Vec<f64>,
+    #[serde(skip)]
+    normals: Vec<Vec3d>,
+    #[serde(skip)]
+    min_height: f64,
+    #[serde(skip)]
+    max_height: f64,
+}
+
+impl Heightfield {
+    /// Loads the image at `path` right away
+    pub fn from_file(
+        path: &str,
+        origin: Vec3d,
+        size: Vec3d,
+        m: Material,
+    ) -> Result<Heightfield, CraycrayError> {
+        let mut field = Heightfield {
+            material: m,
+            path: path.to_string(),
+            origin: origin,
+            size: size,
+            nx: 0,
+            nz: 0,
+            heights: Vec::new(),
+            normals: Vec::new(),
+            min_height: 0.0,
+            max_height: 0.0,
+        };
+        field.load(Path::new(""))?;
+        Ok(field)
+    }
+
+    /// Reads the image, needed after deserializing
+    pub fn load(&mut self, base_dir: &Path) -> Result<(), CraycrayError> {
+        let map = load_heightmap(&base_dir.join(&self.path))?;
+        self.nx = map.width;
+        self.nz = map.height;
+        self.heights = map.samples.into_iter().map(|h| h * self.size.y).collect();
+        self.min_height = self.heights.iter().cloned().fold(f64::INFINITY, f64::min);
+        self.max_height = self.heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+        let normals = {
+            let nx = self.nx;
+            (0..self.nz)
+                .flat_map(|j| (0..nx).map(move |i| (i, j)))
+                .map(|(i, j)| self.vertex_normal(i, j))
+                .collect()
+        };
+        self.normals = normals;
+        Ok(())
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.size.x / (self.nx - 1) as f64,
+            self.size.z / (self.nz - 1) as f64,
+        )
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[j * self.nx + i]
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3d {
+        let (cx, cz) = self.cell_size();
+        self.origin + Vec3d::new(i as f64 * cx, self.height(i, j), j as f64 * cz)
+    }
+
+    // Normal from the slopes to the neighbouring vertices
+    fn vertex_normal(&self, i: usize, j: usize) -> Vec3d {
+        let (cx, cz) = self.cell_size();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+        let dx = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f64 * cx);
+        let dz = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f64 * cz);
+        Vec3d::new(-dx, 1.0, -dz).normalize()
+    }
+
+    fn bbox(&self) -> Aabb {
+        Aabb::new(
+            self.origin + Vec3d::new(0.0, self.min_height, 0.0),
+            self.origin + Vec3d::new(self.size.x, self.max_height, self.size.z),
+        )
+    }
+
+    // Triangle hits of the cell with its lower corner at (i, j), as the
+    // distance and the interpolated normal
+    fn cell_hits(&self, p0: Vec3d, d: Vec3d, i: usize, j: usize) -> Vec<(f64, Vec3d)> {
+        let corners = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)];
+        let mut hits: Vec<(f64, Vec3d)> = [[0, 1, 2], [0, 2, 3]]
+            .iter()
+            .filter_map(|tri| {
+                let (a, b, c) = (corners[tri[0]], corners[tri[1]], corners[tri[2]]);
+                let normal = |(i, j): (usize, usize)| self.normals[j * self.nx + i];
+                line_triangle_uv(
+                    p0,
+                    d,
+                    self.vertex(a.0, a.1),
+                    self.vertex(b.0, b.1),
+                    self.vertex(c.0, c.1),
+                ).map(|(t, u, v)| {
+                    let n = normal(a) * (1.0 - u - v) + normal(b) * u + normal(c) * v;
+                    (t, n.normalize())
+                })
+            })
+            .collect();
+        if hits.len() == 2 && hits[1].0 < hits[0].0 {
+            hits.swap(0, 1);
+        }
+        hits
+    }
+
+    // Walks the grid cells along the line from `t_min` on with a 2D DDA,
+    // collecting the triangle hits in order
+    fn walk(&self, p0: Vec3d, d: Vec3d, t_min: f64, first_only: bool) -> Vec<(f64, Vec3d)> {
+        let mut hits = Vec::new();
+        if self.nx < 2 || self.nz < 2 {
+            return hits;
+        }
+
+        let bbox = self.bbox();
+        let inv_d = Vec3d::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let (t_enter, t_exit) = match bbox.line_range(p0, inv_d) {
+            Some((t0, t1)) if t1 >= t_min => (t0.max(t_min), t1),
+            _ => return hits,
+        };
+
+        let (cx, cz) = self.cell_size();
+        let start = p0 + d * t_enter - self.origin;
+        let clamp_cell = |x: f64, n: usize| (x.floor().max(0.0) as usize).min(n - 2);
+        let mut i = clamp_cell(start.x / cx, self.nx);
+        let mut j = clamp_cell(start.z / cz, self.nz);
+
+        // Distance to the next cell boundary and between boundaries per axis
+        let axis = |pos: f64, dir: f64, cell: usize, size: f64| if dir > 0.0 {
+            (((cell + 1) as f64 * size - pos) / dir, size / dir)
+        } else if dir < 0.0 {
+            ((cell as f64 * size - pos) / dir, -size / dir)
+        } else {
+            (f64::INFINITY, f64::INFINITY)
+        };
+        let (mut next_x, delta_x) = axis(start.x, d.x, i, cx);
+        let (mut next_z, delta_z) = axis(start.z, d.z, j, cz);
+        next_x += t_enter;
+        next_z += t_enter;
+
+        let mut t = t_enter;
+        loop {
+            let t_next = next_x.min(next_z).min(t_exit);
+
+            // Skip the triangles when the ray passes above or below the cell
+            let (y0, y1) = (p0.y + d.y * t, p0.y + d.y * t_next);
+            let cell = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)];
+            let heights = cell.iter().map(|&(a, b)| self.height(a, b));
+            let (lo, hi) = heights.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), h| {
+                (lo.min(h), hi.max(h))
+            });
+            let y_lo = y0.min(y1) - self.origin.y;
+            let y_hi = y0.max(y1) - self.origin.y;
+            if y_lo <= hi + EPSILON && y_hi >= lo - EPSILON {
+                for hit in self.cell_hits(p0, d, i, j) {
+                    // A hit on the shared edge shows up in both cells
+                    let repeated = hits.last().map_or(false, |last: &(f64, Vec3d)| {
+                        hit.0 - last.0 < EPSILON
+                    });
+                    if hit.0 >= t_min && !repeated {
+                        hits.push(hit);
+                        if first_only {
+                            return hits;
+                        }
+                    }
+                }
+            }
+
+            if t_next >= t_exit {
+                break;
+            }
+            if next_x < next_z {
+                if (d.x > 0.0 && i + 2 >= self.nx) || (d.x < 0.0 && i == 0) {
+                    break;
+                }
+                i = if d.x > 0.0 { i + 1 } else { i - 1 };
+                t = next_x;
+                next_x += delta_x;
+            } else {
+                if (d.z > 0.0 && j + 2 >= self.nz) || (d.z < 0.0 && j == 0) {
+                    break;
+                }
+                j = if d.z > 0.0 { j + 1 } else { j - 1 };
+                t = next_z;
+                next_z += delta_z;
+            }
+        }
+        hits
+    }
+}
+
+impl Intersectable for Heightfield {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.walk(p0, d, EPSILON, true).first().map(|&(t, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.walk(p0, d, EPSILON, true)
+            .first()
+            .map(|&(t, n)| Intersection::new(&self.material, p0 + d * t, n))
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.walk(p0, d, f64::NEG_INFINITY, false)
+            .into_iter()
+            .map(|(t, n)| (t, Intersection::new(&self.material, p0 + d * t, n)))
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.heights.is_empty() {
+            None
+        } else {
+            Some(self.bbox())
+        }
+    }
+}
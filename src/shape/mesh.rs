@@ -0,0 +1,249 @@
+use cgmath::*;
+use shape::*;
+use color;
+use bvh::Bvh;
+use scene::CraycrayError;
+use subdivision::{stray_crease, ControlMesh};
+use shape::triangle::{intersect_triangle, line_triangle_uv, triangle_normal};
+
+// Interpolated normals shorter than this fall back to the face normal
+const EPSILON: f64 = 1e-6;
+
+/// Triangle mesh with shared vertex and index buffers. Hits interpolate the
+/// vertex normals and colors
+#[derive(Serialize, Deserialize)]
+pub struct Mesh {
+    material: Material,
+    vertices: Vec<Vec3d>,
+    indices: Vec<[usize; 3]>,
+    /// Per vertex normals, generated if the source has none
+    #[serde(default)]
+    normals: Vec<Vec3d>,
+    /// Per vertex colors, empty if the source has none
+    #[serde(default)]
+    colors: Vec<Color>,
+    /// Subdivision levels applied when the scene is loaded
+    #[serde(default)]
+    subdivision: usize,
+    /// Edges that stay sharp while subdividing, as vertex pairs with the
+    /// number of levels they last
+    #[serde(default)]
+    creases: Vec<(usize, usize, f64)>,
+    #[serde(skip)]
+    bvh: Bvh,
+}
+
+fn color_material(c: Color) -> Material {
+    Material {
+        diffuse_color: c,
+        ambient_color: color::BLACK,
+        specular_color: color::WHITE,
+        shininess: 15.0,
+        reflectivity: 0.1,
+        transparency: 0.0,
+        ior: 1.0,
+        trap_color: None,
+    }
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vec3d>, indices: Vec<[usize; 3]>, c: Color) -> Mesh {
+        Mesh::from_material(vertices, indices, color_material(c))
+    }
+
+    pub fn from_material(vertices: Vec<Vec3d>, indices: Vec<[usize; 3]>, m: Material) -> Mesh {
+        Mesh::from_normals(vertices, Vec::new(), indices, m)
+    }
+
+    pub fn new_with_normals(
+        vertices: Vec<Vec3d>,
+        normals: Vec<Vec3d>,
+        indices: Vec<[usize; 3]>,
+        c: Color,
+    ) -> Mesh {
+        Mesh::from_normals(vertices, normals, indices, color_material(c))
+    }
+
+    /// Mesh with a normal for every vertex, they are generated instead if
+    /// the counts don't match
+    pub fn from_normals(
+        vertices: Vec<Vec3d>,
+        normals: Vec<Vec3d>,
+        indices: Vec<[usize; 3]>,
+        m: Material,
+    ) -> Mesh {
+        let mut mesh = Mesh {
+            material: m,
+            vertices: vertices,
+            indices: indices,
+            normals: normals,
+            colors: Vec::new(),
+            subdivision: 0,
+            creases: Vec::new(),
+            bvh: Bvh::default(),
+        };
+        mesh.build_bvh();
+        mesh
+    }
+
+    /// Sets a color for every vertex
+    pub fn with_colors(mut self, colors: Vec<Color>) -> Mesh {
+        self.colors = colors;
+        self
+    }
+
+    /// Builds the triangle hierarchy and the missing vertex normals, needed
+    /// after deserializing
+    pub fn build_bvh(&mut self) {
+        if self.normals.len() != self.vertices.len() {
+            self.normals = vertex_normals(&self.vertices, &self.indices);
+        }
+        self.bvh = {
+            let boxes = self.indices.iter().enumerate().map(|(i, tri)| {
+                let (a, b, c) = self.corners(tri);
+                (i, Aabb::from_points([a, b, c].iter().cloned()))
+            });
+            Bvh::build(boxes)
+        };
+    }
+
+    /// Checks that the triangles of a deserialized mesh only use its vertices
+    /// and that the creases are edges of the triangles
+    pub fn validate(&self) -> Result<(), CraycrayError> {
+        let n = self.vertices.len();
+        if let Some(i) = self.indices.iter().flat_map(|tri| tri.iter()).find(|&&i| i >= n) {
+            return Err(CraycrayError::InvalidShape(format!(
+                "mesh index {} out of range for {} vertices",
+                i, n
+            )));
+        }
+        match stray_crease(self.indices.iter().map(|tri| &tri[..]), &self.creases) {
+            Some((a, b)) => Err(CraycrayError::InvalidShape(format!(
+                "mesh crease {}-{} is not an edge of a triangle",
+                a, b
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the mesh by its refinement if the scene file asks for
+    /// subdivision, the indices must have been validated
+    pub fn subdivide(&mut self) {
+        if self.subdivision == 0 {
+            return;
+        }
+        let faces = self.indices.iter().map(|tri| tri.to_vec()).collect();
+        let mut control = ControlMesh::new(self.vertices.clone(), faces);
+        if self.colors.len() == self.vertices.len() {
+            control = control.with_colors(self.colors.clone());
+        }
+        for &(a, b, sharpness) in &self.creases {
+            control.add_crease(a, b, sharpness);
+        }
+        *self = control
+            .subdivide(self.subdivision)
+            .into_mesh(self.material.clone());
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn corners(&self, tri: &[usize; 3]) -> (Vec3d, Vec3d, Vec3d) {
+        (
+            self.vertices[tri[0]],
+            self.vertices[tri[1]],
+            self.vertices[tri[2]],
+        )
+    }
+
+    // Closest triangle along the ray and its distance
+    fn closest(&self, p0: Vec3d, d: Vec3d) -> Option<(&[usize; 3], f64)> {
+        self.bvh
+            .closest(p0, d, |i| {
+                let (a, b, c) = self.corners(&self.indices[i]);
+                intersect_triangle(p0, d, a, b, c)
+            })
+            .map(|(i, t)| (&self.indices[i], t))
+    }
+
+    // Hit on `tri` with the barycentric weights `u` and `v` of its second and
+    // third corner
+    fn intersection(&self, tri: &[usize; 3], point: Vec3d, u: f64, v: f64) -> Intersection {
+        let w = 1.0 - u - v;
+        let (n0, n1, n2) = (self.normals[tri[0]], self.normals[tri[1]], self.normals[tri[2]]);
+        let n = n0 * w + n1 * u + n2 * v;
+        let normal = if n.magnitude2() > EPSILON {
+            n.normalize()
+        } else {
+            let (a, b, c) = self.corners(tri);
+            triangle_normal(a, b, c)
+        };
+
+        let inter = Intersection::new(&self.material, point, normal);
+        if self.colors.len() == self.vertices.len() {
+            let (c0, c1, c2) = (self.colors[tri[0]], self.colors[tri[1]], self.colors[tri[2]]);
+            inter.with_color(c0 * w + c1 * u + c2 * v)
+        } else {
+            inter
+        }
+    }
+}
+
+/// Normals of the vertices, the average of the face normals around them
+/// weighted by the angle of the faces at the vertex
+pub fn vertex_normals(vertices: &[Vec3d], indices: &[[usize; 3]]) -> Vec<Vec3d> {
+    let mut normals = vec![Vec3d::zero(); vertices.len()];
+    for tri in indices {
+        let face = (vertices[tri[1]] - vertices[tri[0]]).cross(vertices[tri[2]] - vertices[tri[0]]);
+        if face.magnitude2() == 0.0 {
+            continue;
+        }
+        let face = face.normalize();
+
+        for k in 0..3 {
+            let p = vertices[tri[k]];
+            let e1 = vertices[tri[(k + 1) % 3]] - p;
+            let e2 = vertices[tri[(k + 2) % 3]] - p;
+            let cos = e1.dot(e2) / (e1.magnitude() * e2.magnitude());
+            normals[tri[k]] += face * cos.clamp(-1.0, 1.0).acos();
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| if n.magnitude2() > 0.0 { n.normalize() } else { n })
+        .collect()
+}
+
+impl Intersectable for Mesh {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.closest(p0, d).map(|(_, t)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.closest(p0, d).and_then(|(tri, _)| {
+            let (a, b, c) = self.corners(tri);
+            line_triangle_uv(p0, d, a, b, c)
+                .map(|(t, u, v)| self.intersection(tri, p0 + d * t, u, v))
+        })
+    }
+
+    // Tests every triangle, CSG on big meshes is slow
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        let mut crossings = self.indices
+            .iter()
+            .filter_map(|tri| {
+                let (a, b, c) = self.corners(tri);
+                line_triangle_uv(p0, d, a, b, c)
+                    .map(|(t, u, v)| (t, self.intersection(tri, p0 + d * t, u, v)))
+            })
+            .collect::<Vec<_>>();
+        sort_crossings(&mut crossings);
+        crossings
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bbox()
+    }
+}
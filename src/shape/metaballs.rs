@@ -0,0 +1,335 @@
+use std::f64;
+use std::cmp::Ordering;
+
+use cgmath::*;
+use shape::*;
+use color;
+
+// Hits closer than this to the ray origin are the surface the ray left
+const EPSILON: f64 = 1e-3;
+// Samples per smallest ball radius while bracketing roots
+const STEPS_PER_RADIUS: f64 = 16.0;
+const BISECTION_STEPS: usize = 40;
+
+/// Center of a metaball field, its influence ends at `radius`. Balls
+/// without a positive radius have no influence
+#[derive(Serialize, Deserialize)]
+pub struct Ball {
+    center: Vec3d,
+    radius: f64,
+    #[serde(default = "default_weight")]
+    weight: f64,
+    /// The center oscillates by `amplitude` around its rest position
+    #[serde(default = "zero")]
+    amplitude: Vec3d,
+    /// Angular frequency of the oscillation in radians per second
+    #[serde(default)]
+    frequency: f64,
+    #[serde(default)]
+    phase: f64,
+    #[serde(skip, default = "zero")]
+    offset: Vec3d,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn zero() -> Vec3d {
+    Vec3d::zero()
+}
+
+impl Ball {
+    pub fn new(center: Vec3d, radius: f64, weight: f64) -> Ball {
+        Ball {
+            center: center,
+            radius: radius,
+            weight: weight,
+            amplitude: Vec3d::zero(),
+            frequency: 0.0,
+            phase: 0.0,
+            offset: Vec3d::zero(),
+        }
+    }
+
+    /// Makes the ball oscillate around its center when the scene steps
+    pub fn oscillating(mut self, amplitude: Vec3d, frequency: f64, phase: f64) -> Ball {
+        self.amplitude = amplitude;
+        self.frequency = frequency;
+        self.phase = phase;
+        self
+    }
+
+    fn position(&self) -> Vec3d {
+        self.center + self.offset
+    }
+
+    // A radius that isn't positive would stall the root sampling
+    fn has_influence(&self) -> bool {
+        self.radius > 0.0
+    }
+
+    fn is_animated(&self) -> bool {
+        self.frequency != 0.0 && self.amplitude != Vec3d::zero()
+    }
+
+    // Smooth falloff w * (1 - r²/R²)³, zero outside of the radius
+    fn field(&self, p: Vec3d) -> f64 {
+        if !self.has_influence() {
+            return 0.0;
+        }
+        let x = (p - self.position()).magnitude2() / (self.radius * self.radius);
+        if x >= 1.0 {
+            0.0
+        } else {
+            let k = 1.0 - x;
+            self.weight * k * k * k
+        }
+    }
+
+    fn gradient(&self, p: Vec3d) -> Vec3d {
+        let rel = p - self.position();
+        let r2 = self.radius * self.radius;
+        let x = rel.magnitude2() / r2;
+        if x >= 1.0 || !self.has_influence() {
+            Vec3d::zero()
+        } else {
+            let k = 1.0 - x;
+            rel * (-6.0 * self.weight * k * k / r2)
+        }
+    }
+
+    // Line range inside the ball's radius of influence
+    fn range(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, f64)> {
+        if !self.has_influence() {
+            return None;
+        }
+        let oc = p0 - self.position();
+        let b = oc.dot(d);
+        let delta = b * b - (oc.dot(oc) - self.radius * self.radius);
+        if delta <= 0.0 {
+            None
+        } else {
+            let delta_sq = delta.sqrt();
+            Some((-b - delta_sq, -b + delta_sq))
+        }
+    }
+}
+
+/// Blobby surface where the summed field of the balls reaches `threshold`
+#[derive(Serialize, Deserialize)]
+pub struct Metaballs {
+    material: Material,
+    balls: Vec<Ball>,
+    #[serde(default = "default_threshold")]
+    threshold: f64,
+}
+
+fn default_threshold() -> f64 {
+    0.5
+}
+
+impl Metaballs {
+    pub fn new(balls: Vec<Ball>, threshold: f64, c: Color) -> Metaballs {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Metaballs::from_material(balls, threshold, material)
+    }
+
+    pub fn from_material(balls: Vec<Ball>, threshold: f64, m: Material) -> Metaballs {
+        Metaballs {
+            material: m,
+            balls: balls,
+            threshold: threshold,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.balls.iter().any(|b| b.is_animated())
+    }
+
+    /// Moves the oscillating balls to where they are at `time` seconds,
+    /// true if any of them moved
+    pub fn animate(&mut self, time: f64) -> bool {
+        let mut moved = false;
+        for ball in self.balls.iter_mut().filter(|b| b.is_animated()) {
+            ball.offset = ball.amplitude * (ball.frequency * time + ball.phase).sin();
+            moved = true;
+        }
+        moved
+    }
+
+    // Field minus the threshold, positive inside
+    fn value(&self, p: Vec3d) -> f64 {
+        self.balls.iter().map(|b| b.field(p)).sum::<f64>() - self.threshold
+    }
+
+    fn normal(&self, p: Vec3d) -> Vec3d {
+        // The field falls off outwards
+        -self.balls
+            .iter()
+            .fold(Vec3d::zero(), |g, b| g + b.gradient(p))
+            .normalize()
+    }
+
+    // Merged line ranges where any ball has influence, the field is zero
+    // everywhere else
+    fn intervals(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, f64)> {
+        let mut ranges: Vec<(f64, f64)> =
+            self.balls.iter().filter_map(|b| b.range(p0, d)).collect();
+        ranges.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        let mut merged: Vec<(f64, f64)> = Vec::new();
+        for (t0, t1) in ranges {
+            match merged.last_mut() {
+                Some(last) if t0 <= last.1 => last.1 = last.1.max(t1),
+                _ => merged.push((t0, t1)),
+            }
+        }
+        merged
+    }
+
+    // Roots of the field along the line after `t_min`. Each interval is
+    // sampled to bracket the sign changes, which are refined by bisection
+    fn roots(&self, p0: Vec3d, d: Vec3d, t_min: f64, first_only: bool) -> Vec<f64> {
+        let min_radius = self.balls
+            .iter()
+            .filter(|b| b.has_influence())
+            .map(|b| b.radius)
+            .fold(f64::INFINITY, f64::min);
+        let step = min_radius / STEPS_PER_RADIUS;
+        let value = |t: f64| self.value(p0 + d * t);
+        let mut roots = Vec::new();
+
+        for (t0, t1) in self.intervals(p0, d) {
+            if t1 <= t_min {
+                continue;
+            }
+            let mut a = t0.max(t_min);
+            let mut va = value(a);
+            while a < t1 {
+                let b = (a + step).min(t1);
+                let vb = value(b);
+                if (va > 0.0) != (vb > 0.0) {
+                    roots.push(bisect(&value, a, b, va));
+                    if first_only {
+                        return roots;
+                    }
+                }
+                a = b;
+                va = vb;
+            }
+        }
+        roots
+    }
+}
+
+// Root of `f` in [a, b], `fa` is f(a) and f(b) has the other sign
+fn bisect<F: Fn(f64) -> f64>(f: &F, mut a: f64, mut b: f64, fa: f64) -> f64 {
+    let a_inside = fa > 0.0;
+    for _ in 0..BISECTION_STEPS {
+        let mid = 0.5 * (a + b);
+        if (f(mid) > 0.0) == a_inside {
+            a = mid;
+        } else {
+            b = mid;
+        }
+    }
+    0.5 * (a + b)
+}
+
+impl Intersectable for Metaballs {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.roots(p0, d, EPSILON, true).first().cloned()
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.intersect_dist(p0, d).map(|t| {
+            let q = p0 + d * t;
+            Intersection::new(&self.material, q, self.normal(q))
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.roots(p0, d, f64::NEG_INFINITY, false)
+            .into_iter()
+            .map(|t| {
+                let q = p0 + d * t;
+                (t, Intersection::new(&self.material, q, self.normal(q)))
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.balls.is_empty() {
+            return None;
+        }
+        let bbox = self.balls
+            .iter()
+            .filter(|b| b.has_influence())
+            .fold(Aabb::empty(), |bbox, b| {
+                let r = Vec3d::new(b.radius, b.radius, b.radius);
+                bbox.union(Aabb::new(b.position() - r, b.position() + r))
+            });
+        Some(bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3d, b: Vec3d) {
+        assert!((a - b).magnitude() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn single_ball() {
+        let (radius, weight, threshold) = (2.0, 1.5, 0.5);
+        let balls = Metaballs::new(
+            vec![Ball::new(Vec3d::zero(), radius, weight)],
+            threshold,
+            color::WHITE,
+        );
+        // Distance where w * (1 - r²/R²)³ falls to the threshold
+        let r = radius * (1.0 - (threshold / weight).powf(1.0 / 3.0)).sqrt();
+
+        let p0 = Vec3d::new(3.0, 4.0, -5.0);
+        let d = -p0.normalize();
+        let hit = balls.intersect(p0, d).expect("ball hit");
+        let radial = p0.normalize();
+        assert_close(hit.point, radial * r);
+        assert_close(hit.normal, radial);
+    }
+
+    #[test]
+    fn zero_radius_ball() {
+        let p0 = Vec3d::new(0.0, 0.0, -5.0);
+        let d = Vec3d::new(0.0, 0.0, 1.0);
+        let empty = Metaballs::new(vec![Ball::new(Vec3d::zero(), 0.0, 1.0)], 0.5, color::WHITE);
+        assert_eq!(empty.intersect_dist(p0, d), None);
+        assert!(empty.crossings(p0, d).is_empty());
+
+        // Leaves the other balls alone
+        let with_ball = Metaballs::new(
+            vec![Ball::new(Vec3d::zero(), 0.0, 1.0), Ball::new(Vec3d::zero(), 1.0, 1.0)],
+            0.5,
+            color::WHITE,
+        );
+        let r = (1.0 - 0.5f64.powf(1.0 / 3.0)).sqrt();
+        let t = with_ball.intersect_dist(p0, d).expect("ball hit");
+        assert!((t - (5.0 - r)).abs() < 1e-6, "{} != {}", t, 5.0 - r);
+    }
+}
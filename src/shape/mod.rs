@@ -1,27 +1,137 @@
 pub mod sphere;
 pub mod plane;
+pub mod triangle;
+pub mod mesh;
+pub mod bezier;
+pub mod cuboid;
+pub mod cylinder;
+pub mod cone;
+pub mod torus;
+pub mod disk;
+pub mod quad;
+pub mod csg;
+pub mod transformed;
+pub mod group;
+pub mod sdf;
+pub mod fractal;
+pub mod heightfield;
+pub mod metaballs;
 
+use aabb::Aabb;
 use vec3d::Vec3d;
 use material::Material;
 use self::sphere::Sphere;
 use self::plane::Plane;
+use self::triangle::Triangle;
+use self::mesh::Mesh;
+use self::bezier::BezierPatch;
+use self::cuboid::Cuboid;
+use self::cylinder::Cylinder;
+use self::cone::Cone;
+use self::torus::Torus;
+use self::disk::Disk;
+use self::quad::Quad;
+use self::csg::{Csg, CsgOp};
+use self::transformed::Transformed;
+use self::group::Group;
+use self::sdf::{Sdf, SdfNode};
+use self::fractal::{Fractal, FractalKind};
+use self::heightfield::Heightfield;
+use self::metaballs::{Ball, Metaballs};
+use scene::CraycrayError;
+use std::path::Path;
+use transform::Transform;
 use color::Color;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct Intersection<'a> {
     pub material: &'a Material,
     pub point: Vec3d,
+    /// Outward surface normal, the tracer flips it for hits from the inside
     pub normal: Vec3d,
+    /// Surface coordinates, for shapes that have a parametrization
+    pub uv: Option<(f64, f64)>,
+    /// Orbit trap of fractal surfaces in [0, 1]
+    pub trap: Option<f64>,
+    /// Interpolated vertex color, replaces the material's diffuse color
+    pub color: Option<Color>,
+}
+
+impl<'a> Intersection<'a> {
+    pub fn new(material: &'a Material, point: Vec3d, normal: Vec3d) -> Intersection<'a> {
+        Intersection {
+            material: material,
+            point: point,
+            normal: normal,
+            uv: None,
+            trap: None,
+            color: None,
+        }
+    }
+
+    pub fn with_uv(mut self, u: f64, v: f64) -> Intersection<'a> {
+        self.uv = Some((u, v));
+        self
+    }
+
+    pub fn with_trap(mut self, trap: f64) -> Intersection<'a> {
+        self.trap = Some(trap);
+        self
+    }
+
+    pub fn with_color(mut self, color: Color) -> Intersection<'a> {
+        self.color = Some(color);
+        self
+    }
+
+    /// Diffuse color at the hit, the vertex color if there is one. The material's trap
+    /// color is blended in by the orbit trap
+    pub fn diffuse_color(&self) -> Color {
+        let diffuse = self.color.unwrap_or(self.material.diffuse_color);
+        match (self.trap, self.material.trap_color) {
+            (Some(t), Some(c)) => diffuse * (1.0 - t) + c * t,
+            _ => diffuse,
+        }
+    }
+}
+
+/// Sorts crossings by their distance along the line
+pub fn sort_crossings(crossings: &mut Vec<(f64, Intersection)>) {
+    crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
 }
 
 pub trait Intersectable {
     fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64>;
     fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection>;
+    /// Every point where the whole line `p0 + t * d` crosses the surface,
+    /// sorted by `t` and including negative distances. Normals point out of
+    /// the shape, CSG tells entries from exits by them
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)>;
+    /// Bounds of the shape, None for unbounded shapes
+    fn bounding_box(&self) -> Option<Aabb>;
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum Shape {
     Sphere(Sphere),
     Plane(Plane),
+    Triangle(Triangle),
+    Mesh(Mesh),
+    Box(Cuboid),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Disk(Disk),
+    Quad(Quad),
+    Csg(Csg),
+    Transformed(Transformed),
+    Group(Group),
+    Sdf(Sdf),
+    Fractal(Fractal),
+    Heightfield(Heightfield),
+    Metaballs(Metaballs),
 }
 
 impl Shape {
@@ -40,6 +150,334 @@ impl Shape {
     pub fn new_plane_material(point: Vec3d, normal: Vec3d, m: Material) -> Shape {
         Shape::Plane(Plane::from_material(point, normal, m))
     }
+
+    pub fn new_triangle(a: Vec3d, b: Vec3d, c: Vec3d, color: Color) -> Shape {
+        Shape::Triangle(Triangle::new(a, b, c, color))
+    }
+
+    pub fn new_triangle_material(a: Vec3d, b: Vec3d, c: Vec3d, m: Material) -> Shape {
+        Shape::Triangle(Triangle::from_material(a, b, c, m))
+    }
+
+    pub fn new_mesh(vertices: Vec<Vec3d>, indices: Vec<[usize; 3]>, c: Color) -> Shape {
+        Shape::Mesh(Mesh::new(vertices, indices, c))
+    }
+
+    pub fn new_mesh_material(vertices: Vec<Vec3d>, indices: Vec<[usize; 3]>, m: Material) -> Shape {
+        Shape::Mesh(Mesh::from_material(vertices, indices, m))
+    }
+
+    /// Mesh tessellated from the patches, within `tolerance` of the surface
+    pub fn new_bezier(patches: &[BezierPatch], tolerance: f64, c: Color) -> Shape {
+        let (vertices, normals, indices) = bezier::tessellate(patches, tolerance);
+        Shape::Mesh(Mesh::new_with_normals(vertices, normals, indices, c))
+    }
+
+    pub fn new_bezier_material(patches: &[BezierPatch], tolerance: f64, m: Material) -> Shape {
+        let (vertices, normals, indices) = bezier::tessellate(patches, tolerance);
+        Shape::Mesh(Mesh::from_normals(vertices, normals, indices, m))
+    }
+
+    pub fn new_box(min: Vec3d, max: Vec3d, c: Color) -> Shape {
+        Shape::Box(Cuboid::new(min, max, c))
+    }
+
+    pub fn new_box_material(min: Vec3d, max: Vec3d, m: Material) -> Shape {
+        Shape::Box(Cuboid::from_material(min, max, m))
+    }
+
+    /// Box rotated around its center by `rotation` (x, y, z angles in radians)
+    pub fn new_oriented_box_material(
+        min: Vec3d,
+        max: Vec3d,
+        rotation: Vec3d,
+        m: Material,
+    ) -> Shape {
+        Shape::Box(Cuboid::from_material(min, max, m).rotated(rotation))
+    }
+
+    pub fn new_cylinder(start: Vec3d, end: Vec3d, radius: f64, c: Color) -> Shape {
+        Shape::Cylinder(Cylinder::new(start, end, radius, c))
+    }
+
+    pub fn new_cylinder_material(start: Vec3d, end: Vec3d, radius: f64, m: Material) -> Shape {
+        Shape::Cylinder(Cylinder::from_material(start, end, radius, m))
+    }
+
+    pub fn new_cone(
+        start: Vec3d,
+        end: Vec3d,
+        start_radius: f64,
+        end_radius: f64,
+        c: Color,
+    ) -> Shape {
+        Shape::Cone(Cone::new(start, end, start_radius, end_radius, c))
+    }
+
+    pub fn new_cone_material(
+        start: Vec3d,
+        end: Vec3d,
+        start_radius: f64,
+        end_radius: f64,
+        m: Material,
+    ) -> Shape {
+        Shape::Cone(Cone::from_material(start, end, start_radius, end_radius, m))
+    }
+
+    pub fn new_torus(
+        center: Vec3d,
+        axis: Vec3d,
+        major_radius: f64,
+        minor_radius: f64,
+        c: Color,
+    ) -> Shape {
+        Shape::Torus(Torus::new(center, axis, major_radius, minor_radius, c))
+    }
+
+    pub fn new_torus_material(
+        center: Vec3d,
+        axis: Vec3d,
+        major_radius: f64,
+        minor_radius: f64,
+        m: Material,
+    ) -> Shape {
+        Shape::Torus(Torus::from_material(center, axis, major_radius, minor_radius, m))
+    }
+
+    pub fn new_disk(center: Vec3d, normal: Vec3d, radius: f64, c: Color) -> Shape {
+        Shape::Disk(Disk::new(center, normal, radius, c))
+    }
+
+    pub fn new_disk_material(center: Vec3d, normal: Vec3d, radius: f64, m: Material) -> Shape {
+        Shape::Disk(Disk::from_material(center, normal, radius, m))
+    }
+
+    pub fn new_quad(corner: Vec3d, edge_u: Vec3d, edge_v: Vec3d, c: Color) -> Shape {
+        Shape::Quad(Quad::new(corner, edge_u, edge_v, c))
+    }
+
+    pub fn new_quad_material(corner: Vec3d, edge_u: Vec3d, edge_v: Vec3d, m: Material) -> Shape {
+        Shape::Quad(Quad::from_material(corner, edge_u, edge_v, m))
+    }
+
+    pub fn new_csg(op: CsgOp, left: Shape, right: Shape) -> Shape {
+        Shape::Csg(Csg::new(op, left, right))
+    }
+
+    /// Places `shape` by `transform`, the shape data is shared with every
+    /// other holder of the `Arc`
+    pub fn new_transformed(shape: Arc<Shape>, transform: Transform) -> Shape {
+        Shape::Transformed(Transformed::new(shape, transform))
+    }
+
+    /// Group node moving `children` by `transform`
+    pub fn new_group(name: Option<String>, transform: Transform, children: Vec<Shape>) -> Shape {
+        Shape::Group(Group::new(name, transform, children))
+    }
+
+    pub fn new_sdf(root: SdfNode, c: Color) -> Shape {
+        Shape::Sdf(Sdf::new(root, c))
+    }
+
+    pub fn new_sdf_material(root: SdfNode, m: Material) -> Shape {
+        Shape::Sdf(Sdf::from_material(root, m))
+    }
+
+    pub fn new_fractal(kind: FractalKind, center: Vec3d, scale: f64, c: Color) -> Shape {
+        Shape::Fractal(Fractal::new(kind, center, scale, c))
+    }
+
+    pub fn new_fractal_material(
+        kind: FractalKind,
+        center: Vec3d,
+        scale: f64,
+        m: Material,
+    ) -> Shape {
+        Shape::Fractal(Fractal::from_material(kind, center, scale, m))
+    }
+
+    /// Terrain from the grayscale image at `path`
+    pub fn new_heightfield_material(
+        path: &str,
+        origin: Vec3d,
+        size: Vec3d,
+        m: Material,
+    ) -> Result<Shape, CraycrayError> {
+        Heightfield::from_file(path, origin, size, m).map(Shape::Heightfield)
+    }
+
+    pub fn new_metaballs(balls: Vec<Ball>, threshold: f64, c: Color) -> Shape {
+        Shape::Metaballs(Metaballs::new(balls, threshold, c))
+    }
+
+    pub fn new_metaballs_material(balls: Vec<Ball>, threshold: f64, m: Material) -> Shape {
+        Shape::Metaballs(Metaballs::from_material(balls, threshold, m))
+    }
+
+    /// Name of a group or transformed shape
+    pub fn name(&self) -> Option<&str> {
+        match *self {
+            Shape::Group(ref g) => g.name(),
+            Shape::Transformed(ref t) => t.name(),
+            _ => None,
+        }
+    }
+
+    /// Transform of a group or transformed shape
+    pub fn transform(&self) -> Option<&Transform> {
+        match *self {
+            Shape::Group(ref g) => Some(g.transform()),
+            Shape::Transformed(ref t) => Some(t.transform()),
+            _ => None,
+        }
+    }
+
+    /// Depth first search for a named node, this shape included
+    pub fn find_node(&self, name: &str) -> Option<&Shape> {
+        if self.name() == Some(name) {
+            return Some(self);
+        }
+        match *self {
+            Shape::Csg(ref c) => {
+                let (left, right) = c.operands();
+                left.find_node(name).or_else(|| right.find_node(name))
+            }
+            Shape::Transformed(ref t) => t.find_node(name),
+            Shape::Group(ref g) => g.find_node(name),
+            _ => None,
+        }
+    }
+
+    /// Sets the transform of the named node, true if it was found. Bounds of
+    /// the groups on the way are updated
+    pub fn set_node_transform(&mut self, name: &str, transform: &Transform) -> bool {
+        let is_node = self.name() == Some(name);
+        match *self {
+            Shape::Csg(ref mut c) => {
+                let (left, right) = c.operands_mut();
+                left.set_node_transform(name, transform) ||
+                    right.set_node_transform(name, transform)
+            }
+            Shape::Transformed(ref mut t) => {
+                if is_node {
+                    t.set_transform(transform.clone());
+                    true
+                } else {
+                    t.set_node_transform(name, transform)
+                }
+            }
+            Shape::Group(ref mut g) => {
+                if is_node {
+                    g.set_transform(transform.clone());
+                    true
+                } else {
+                    g.set_node_transform(name, transform)
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Calls `f` on every material used by the shape
+    pub fn map_materials<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&mut Material),
+    {
+        match *self {
+            Shape::Sphere(ref mut s) => f(s.material_mut()),
+            Shape::Plane(ref mut p) => f(p.material_mut()),
+            Shape::Triangle(ref mut t) => f(t.material_mut()),
+            Shape::Mesh(ref mut m) => f(m.material_mut()),
+            Shape::Box(ref mut b) => f(b.material_mut()),
+            Shape::Cylinder(ref mut c) => f(c.material_mut()),
+            Shape::Cone(ref mut c) => f(c.material_mut()),
+            Shape::Torus(ref mut t) => f(t.material_mut()),
+            Shape::Disk(ref mut disk) => f(disk.material_mut()),
+            Shape::Quad(ref mut q) => f(q.material_mut()),
+            Shape::Csg(ref mut c) => c.map_materials(f),
+            Shape::Transformed(ref mut t) => t.map_materials(f),
+            Shape::Group(ref mut g) => g.map_materials(f),
+            Shape::Sdf(ref mut sdf) => f(sdf.material_mut()),
+            Shape::Fractal(ref mut fr) => f(fr.material_mut()),
+            Shape::Heightfield(ref mut h) => f(h.material_mut()),
+            Shape::Metaballs(ref mut m) => f(m.material_mut()),
+        }
+    }
+
+    /// Builds the internal acceleration structures of the shape
+    pub fn build_bvh(&mut self) {
+        match *self {
+            Shape::Mesh(ref mut m) => m.build_bvh(),
+            Shape::Csg(ref mut c) => c.build_bvh(),
+            Shape::Transformed(ref mut t) => t.build_bvh(),
+            Shape::Group(ref mut g) => g.build_bvh(),
+            _ => {}
+        }
+    }
+
+    /// Whether `animate` can move anything in the shape
+    pub fn is_animated(&self) -> bool {
+        match *self {
+            Shape::Metaballs(ref m) => m.is_animated(),
+            Shape::Csg(ref c) => {
+                let (left, right) = c.operands();
+                left.is_animated() || right.is_animated()
+            }
+            Shape::Transformed(ref t) => t.is_animated(),
+            Shape::Group(ref g) => g.is_animated(),
+            _ => false,
+        }
+    }
+
+    /// Moves the animated parts of the shape to where they are at `time`
+    /// seconds, true if anything moved
+    pub fn animate(&mut self, time: f64) -> bool {
+        match *self {
+            Shape::Metaballs(ref mut m) => m.animate(time),
+            Shape::Csg(ref mut c) => {
+                let (left, right) = c.operands_mut();
+                let moved = left.animate(time);
+                right.animate(time) || moved
+            }
+            Shape::Transformed(ref mut t) => t.animate(time),
+            Shape::Group(ref mut g) => g.animate(time),
+            _ => false,
+        }
+    }
+
+    /// Loads the files that shapes reference, relative to `base_dir`, and
+    /// checks the shapes read from the scene file
+    pub fn load_resources(&mut self, base_dir: &Path) -> Result<(), CraycrayError> {
+        match *self {
+            Shape::Mesh(ref mut m) => {
+                m.validate()?;
+                m.subdivide();
+                Ok(())
+            }
+            Shape::Heightfield(ref mut h) => h.load(base_dir),
+            Shape::Csg(ref mut c) => {
+                let (left, right) = c.operands_mut();
+                left.load_resources(base_dir)?;
+                right.load_resources(base_dir)
+            }
+            Shape::Transformed(ref mut t) => t.load_resources(base_dir),
+            Shape::Group(ref mut g) => g.load_resources(base_dir),
+            _ => Ok(()),
+        }
+    }
+
+    /// Links every reference to a named prototype, returns the first unknown name
+    pub fn resolve_prototypes(
+        &mut self,
+        prototypes: &HashMap<String, Arc<Shape>>,
+    ) -> Result<(), String> {
+        match *self {
+            Shape::Csg(ref mut c) => c.resolve_prototypes(prototypes),
+            Shape::Transformed(ref mut t) => t.resolve_prototypes(prototypes),
+            Shape::Group(ref mut g) => g.resolve_prototypes(prototypes),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Intersectable for Shape {
@@ -47,6 +485,21 @@ impl Intersectable for Shape {
         match *self {
             Shape::Sphere(ref s) => s.intersect_dist(p0, d),
             Shape::Plane(ref p) => p.intersect_dist(p0, d),
+            Shape::Triangle(ref t) => t.intersect_dist(p0, d),
+            Shape::Mesh(ref m) => m.intersect_dist(p0, d),
+            Shape::Box(ref b) => b.intersect_dist(p0, d),
+            Shape::Cylinder(ref c) => c.intersect_dist(p0, d),
+            Shape::Cone(ref c) => c.intersect_dist(p0, d),
+            Shape::Torus(ref t) => t.intersect_dist(p0, d),
+            Shape::Disk(ref disk) => disk.intersect_dist(p0, d),
+            Shape::Quad(ref q) => q.intersect_dist(p0, d),
+            Shape::Csg(ref c) => c.intersect_dist(p0, d),
+            Shape::Transformed(ref t) => t.intersect_dist(p0, d),
+            Shape::Group(ref g) => g.intersect_dist(p0, d),
+            Shape::Sdf(ref sdf) => sdf.intersect_dist(p0, d),
+            Shape::Fractal(ref fr) => fr.intersect_dist(p0, d),
+            Shape::Heightfield(ref h) => h.intersect_dist(p0, d),
+            Shape::Metaballs(ref m) => m.intersect_dist(p0, d),
         }
     }
 
@@ -54,6 +507,65 @@ impl Intersectable for Shape {
         match *self {
             Shape::Sphere(ref s) => s.intersect(p0, d),
             Shape::Plane(ref p) => p.intersect(p0, d),
+            Shape::Triangle(ref t) => t.intersect(p0, d),
+            Shape::Mesh(ref m) => m.intersect(p0, d),
+            Shape::Box(ref b) => b.intersect(p0, d),
+            Shape::Cylinder(ref c) => c.intersect(p0, d),
+            Shape::Cone(ref c) => c.intersect(p0, d),
+            Shape::Torus(ref t) => t.intersect(p0, d),
+            Shape::Disk(ref disk) => disk.intersect(p0, d),
+            Shape::Quad(ref q) => q.intersect(p0, d),
+            Shape::Csg(ref c) => c.intersect(p0, d),
+            Shape::Transformed(ref t) => t.intersect(p0, d),
+            Shape::Group(ref g) => g.intersect(p0, d),
+            Shape::Sdf(ref sdf) => sdf.intersect(p0, d),
+            Shape::Fractal(ref fr) => fr.intersect(p0, d),
+            Shape::Heightfield(ref h) => h.intersect(p0, d),
+            Shape::Metaballs(ref m) => m.intersect(p0, d),
+        }
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        match *self {
+            Shape::Sphere(ref s) => s.crossings(p0, d),
+            Shape::Plane(ref p) => p.crossings(p0, d),
+            Shape::Triangle(ref t) => t.crossings(p0, d),
+            Shape::Mesh(ref m) => m.crossings(p0, d),
+            Shape::Box(ref b) => b.crossings(p0, d),
+            Shape::Cylinder(ref c) => c.crossings(p0, d),
+            Shape::Cone(ref c) => c.crossings(p0, d),
+            Shape::Torus(ref t) => t.crossings(p0, d),
+            Shape::Disk(ref disk) => disk.crossings(p0, d),
+            Shape::Quad(ref q) => q.crossings(p0, d),
+            Shape::Csg(ref c) => c.crossings(p0, d),
+            Shape::Transformed(ref t) => t.crossings(p0, d),
+            Shape::Group(ref g) => g.crossings(p0, d),
+            Shape::Sdf(ref sdf) => sdf.crossings(p0, d),
+            Shape::Fractal(ref fr) => fr.crossings(p0, d),
+            Shape::Heightfield(ref h) => h.crossings(p0, d),
+            Shape::Metaballs(ref m) => m.crossings(p0, d),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match *self {
+            Shape::Sphere(ref s) => s.bounding_box(),
+            Shape::Plane(ref p) => p.bounding_box(),
+            Shape::Triangle(ref t) => t.bounding_box(),
+            Shape::Mesh(ref m) => m.bounding_box(),
+            Shape::Box(ref b) => b.bounding_box(),
+            Shape::Cylinder(ref c) => c.bounding_box(),
+            Shape::Cone(ref c) => c.bounding_box(),
+            Shape::Torus(ref t) => t.bounding_box(),
+            Shape::Disk(ref disk) => disk.bounding_box(),
+            Shape::Quad(ref q) => q.bounding_box(),
+            Shape::Csg(ref c) => c.bounding_box(),
+            Shape::Transformed(ref t) => t.bounding_box(),
+            Shape::Group(ref g) => g.bounding_box(),
+            Shape::Sdf(ref sdf) => sdf.bounding_box(),
+            Shape::Fractal(ref fr) => fr.bounding_box(),
+            Shape::Heightfield(ref h) => h.bounding_box(),
+            Shape::Metaballs(ref m) => m.bounding_box(),
         }
     }
 }
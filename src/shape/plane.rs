@@ -1,3 +1,5 @@
+use std::f64;
+
 use cgmath::*;
 use shape::*;
 use color;
@@ -17,6 +19,9 @@ impl Plane {
             specular_color: color::BLACK,
             shininess: 15.0,
             reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
         };
         Plane {
             point: point,
@@ -32,6 +37,10 @@ impl Plane {
             material: material,
         }
     }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
 }
 
 impl Intersectable for Plane {
@@ -61,11 +70,7 @@ impl Intersectable for Plane {
                 let dir_scaled = d * t;
                 let q = p0 + dir_scaled;
 
-                Some(Intersection {
-                    material: &self.material,
-                    point: q,
-                    normal: self.normal,
-                })
+                Some(Intersection::new(&self.material, q, self.normal))
             } else {
                 None
             }
@@ -73,4 +78,31 @@ impl Intersectable for Plane {
             None
         }
     }
+
+    // The solid is the half space behind the normal. A line that starts
+    // inside gets an extra crossing at -inf
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        let denom = self.normal.dot(d);
+        let start_inside = if denom == 0.0 {
+            (p0 - self.point).dot(self.normal) < 0.0
+        } else {
+            denom > 0.0
+        };
+
+        let mut crossings = Vec::new();
+        if start_inside {
+            // Facing the ray, so CSG takes it as entering the half-space
+            let inter = Intersection::new(&self.material, p0, self.normal * -1.0);
+            crossings.push((f64::NEG_INFINITY, inter));
+        }
+        if denom != 0.0 {
+            let t = (self.point - p0).dot(self.normal) / denom;
+            crossings.push((t, Intersection::new(&self.material, p0 + d * t, self.normal)));
+        }
+        crossings
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
 }
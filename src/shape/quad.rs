@@ -0,0 +1,105 @@
+use cgmath::*;
+use shape::*;
+use color;
+
+const EPSILON: f64 = 1e-6;
+
+/// Flat, two sided parallelogram spanned by two edges from a corner
+#[derive(Serialize, Deserialize)]
+pub struct Quad {
+    material: Material,
+    corner: Vec3d,
+    edge_u: Vec3d,
+    edge_v: Vec3d,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3d, edge_u: Vec3d, edge_v: Vec3d, c: Color) -> Quad {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::BLACK,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Quad::from_material(corner, edge_u, edge_v, material)
+    }
+
+    pub fn from_material(corner: Vec3d, edge_u: Vec3d, edge_v: Vec3d, m: Material) -> Quad {
+        Quad {
+            material: m,
+            corner: corner,
+            edge_u: edge_u,
+            edge_v: edge_v,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    fn normal(&self) -> Vec3d {
+        self.edge_u.cross(self.edge_v).normalize()
+    }
+
+    // Distance and the (u, v) coordinates along the edges
+    fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, f64, f64)> {
+        self.line_hit(p0, d).and_then(|hit| if hit.0 > EPSILON { Some(hit) } else { None })
+    }
+
+    // Crossing of the whole line, behind the origin included
+    fn line_hit(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, f64, f64)> {
+        let n = self.edge_u.cross(self.edge_v);
+        let denom = n.dot(d);
+        if denom.abs() < EPSILON {
+            return None;
+        }
+
+        let t = (self.corner - p0).dot(n) / denom;
+
+        let rel = p0 + d * t - self.corner;
+        let w = n / n.dot(n);
+        let u = w.dot(rel.cross(self.edge_v));
+        let v = w.dot(self.edge_u.cross(rel));
+        if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+            Some((t, u, v))
+        } else {
+            None
+        }
+    }
+}
+
+impl Intersectable for Quad {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.hit(p0, d).map(|(t, _, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.hit(p0, d).map(|(t, u, v)| {
+            Intersection::new(&self.material, p0 + d * t, self.normal()).with_uv(u, v)
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.line_hit(p0, d)
+            .map(|(t, u, v)| {
+                let inter = Intersection::new(&self.material, p0 + d * t, self.normal());
+                (t, inter.with_uv(u, v))
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let c = self.corner;
+        Some(Aabb::from_points(vec![
+            c,
+            c + self.edge_u,
+            c + self.edge_v,
+            c + self.edge_u + self.edge_v,
+        ]))
+    }
+}
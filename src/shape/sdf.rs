@@ -0,0 +1,291 @@
+use cgmath::*;
+use shape::*;
+use color;
+
+// Distance at which the march counts as a hit
+const HIT_EPSILON: f64 = 1e-4;
+// March limits for shapes without bounds, and for every ray
+pub const MAX_DISTANCE: f64 = 1e3;
+const MAX_STEPS: usize = 512;
+
+/// Node of a signed distance function tree
+#[derive(Serialize, Deserialize)]
+pub enum SdfNode {
+    Sphere { center: Vec3d, radius: f64 },
+    Box { center: Vec3d, half_size: Vec3d },
+    /// Box with its edges rounded by `radius`, within the same extents
+    RoundBox {
+        center: Vec3d,
+        half_size: Vec3d,
+        radius: f64,
+    },
+    /// Torus around the y axis
+    Torus {
+        center: Vec3d,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    /// Segment from `a` to `b` with a radius
+    Capsule { a: Vec3d, b: Vec3d, radius: f64 },
+    /// Union that blends the surfaces within `k` of each other
+    SmoothUnion {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        k: f64,
+    },
+    /// `a` with `b` cut out of it
+    Subtraction { a: Box<SdfNode>, b: Box<SdfNode> },
+    /// Repeats `node` every `period` along each axis, 0 leaves the axis alone
+    Repeat { period: Vec3d, node: Box<SdfNode> },
+}
+
+impl SdfNode {
+    /// Signed distance, negative inside
+    pub fn distance(&self, p: Vec3d) -> f64 {
+        match *self {
+            SdfNode::Sphere { center, radius } => (p - center).magnitude() - radius,
+            SdfNode::Box { center, half_size } => box_distance(p - center, half_size),
+            SdfNode::RoundBox {
+                center,
+                half_size,
+                radius,
+            } => {
+                let inner = half_size - Vec3d::new(radius, radius, radius);
+                box_distance(p - center, inner) - radius
+            }
+            SdfNode::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let q = p - center;
+                let ring = (q.x * q.x + q.z * q.z).sqrt() - major_radius;
+                (ring * ring + q.y * q.y).sqrt() - minor_radius
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let pa = p - a;
+                let ba = b - a;
+                let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+                (pa - ba * h).magnitude() - radius
+            }
+            SdfNode::SmoothUnion { ref a, ref b, k } => {
+                let da = a.distance(p);
+                let db = b.distance(p);
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            }
+            SdfNode::Subtraction { ref a, ref b } => a.distance(p).max(-b.distance(p)),
+            SdfNode::Repeat { period, ref node } => {
+                let wrap = |x: f64, period: f64| if period > 0.0 {
+                    x - period * (x / period).round()
+                } else {
+                    x
+                };
+                let q = Vec3d::new(wrap(p.x, period.x), wrap(p.y, period.y), wrap(p.z, period.z));
+                node.distance(q)
+            }
+        }
+    }
+
+    /// Bounds of the surface, None if it repeats forever
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        match *self {
+            SdfNode::Sphere { center, radius } => {
+                Some(around(center, Vec3d::new(radius, radius, radius)))
+            }
+            SdfNode::Box { center, half_size } |
+            SdfNode::RoundBox {
+                center, half_size, ..
+            } => Some(around(center, half_size)),
+            SdfNode::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let outer = major_radius + minor_radius;
+                Some(around(center, Vec3d::new(outer, minor_radius, outer)))
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let r = Vec3d::new(radius, radius, radius);
+                Some(Aabb::new(a - r, a + r).union(Aabb::new(b - r, b + r)))
+            }
+            SdfNode::SmoothUnion { ref a, ref b, k } => {
+                match (a.bounding_box(), b.bounding_box()) {
+                    // The blend bulges out by at most k / 4
+                    (Some(ba), Some(bb)) => {
+                        let bulge = Vec3d::new(1.0, 1.0, 1.0) * (k * 0.25);
+                        let u = ba.union(bb);
+                        Some(Aabb::new(u.min - bulge, u.max + bulge))
+                    }
+                    _ => None,
+                }
+            }
+            SdfNode::Subtraction { ref a, .. } => a.bounding_box(),
+            SdfNode::Repeat { period, ref node } => {
+                if period.x > 0.0 || period.y > 0.0 || period.z > 0.0 {
+                    None
+                } else {
+                    node.bounding_box()
+                }
+            }
+        }
+    }
+}
+
+/// Sphere traces the distance function `dist` along `p0 + t * d` from `t_min`
+/// to `t_max` and returns the surface crossings. With `from_surface` the march
+/// starts on the surface a secondary ray left and has to get off it before a
+/// hit counts
+pub fn sphere_trace<F>(
+    dist: F,
+    p0: Vec3d,
+    d: Vec3d,
+    t_min: f64,
+    t_max: f64,
+    from_surface: bool,
+    first_only: bool,
+) -> Vec<f64>
+where
+    F: Fn(Vec3d) -> f64,
+{
+    let mut t = t_min;
+    let mut hits = Vec::new();
+    let mut left_surface = !from_surface;
+
+    for _ in 0..MAX_STEPS {
+        if t > t_max {
+            break;
+        }
+        let dist = dist(p0 + d * t).abs();
+        if dist < HIT_EPSILON {
+            if left_surface {
+                hits.push(t);
+                if first_only {
+                    break;
+                }
+                left_surface = false;
+            }
+        } else {
+            left_surface = true;
+        }
+        t += dist.max(HIT_EPSILON);
+    }
+    hits
+}
+
+/// Gradient of the distance function by central differences on a tetrahedron
+pub fn gradient_normal<F>(dist: F, p: Vec3d) -> Vec3d
+where
+    F: Fn(Vec3d) -> f64,
+{
+    let h = HIT_EPSILON;
+    let k = [
+        Vec3d::new(1.0, -1.0, -1.0),
+        Vec3d::new(-1.0, -1.0, 1.0),
+        Vec3d::new(-1.0, 1.0, -1.0),
+        Vec3d::new(1.0, 1.0, 1.0),
+    ];
+    k.iter()
+        .fold(Vec3d::zero(), |n, &k| n + k * dist(p + k * h))
+        .normalize()
+}
+
+fn box_distance(p: Vec3d, half_size: Vec3d) -> f64 {
+    let q = Vec3d::new(p.x.abs(), p.y.abs(), p.z.abs()) - half_size;
+    let outside = Vec3d::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+    outside + q.x.max(q.y).max(q.z).min(0.0)
+}
+
+fn around(center: Vec3d, half_size: Vec3d) -> Aabb {
+    Aabb::new(center - half_size, center + half_size)
+}
+
+/// Surface given by a signed distance function, rendered by sphere tracing
+#[derive(Serialize, Deserialize)]
+pub struct Sdf {
+    material: Material,
+    root: SdfNode,
+}
+
+impl Sdf {
+    pub fn new(root: SdfNode, c: Color) -> Sdf {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Sdf::from_material(root, material)
+    }
+
+    pub fn from_material(root: SdfNode, m: Material) -> Sdf {
+        Sdf {
+            material: m,
+            root: root,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    // Part of the line to march, the bounds or the march limit
+    fn range(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, f64)> {
+        match self.root.bounding_box() {
+            Some(b) => {
+                let inv_d = Vec3d::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+                b.line_range(p0, inv_d).map(|(t0, t1)| (t0, t1.min(MAX_DISTANCE)))
+            }
+            None => Some((-MAX_DISTANCE, MAX_DISTANCE)),
+        }
+    }
+
+    fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.range(p0, d).and_then(|(t0, t1)| {
+            // Inside the bounds the march starts at the ray origin, which
+            // may be on the surface a secondary ray left
+            let from_surface = t0 <= 0.0;
+            sphere_trace(|p| self.root.distance(p), p0, d, t0.max(0.0), t1, from_surface, true)
+                .into_iter()
+                .next()
+        })
+    }
+
+    fn normal(&self, p: Vec3d) -> Vec3d {
+        gradient_normal(|q| self.root.distance(q), p)
+    }
+}
+
+impl Intersectable for Sdf {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.hit(p0, d)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.hit(p0, d).map(|t| {
+            let q = p0 + d * t;
+            Intersection::new(&self.material, q, self.normal(q))
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        match self.range(p0, d) {
+            Some((t0, t1)) => sphere_trace(|p| self.root.distance(p), p0, d, t0, t1, false, false)
+                .into_iter()
+                .map(|t| {
+                    let q = p0 + d * t;
+                    (t, Intersection::new(&self.material, q, self.normal(q)))
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.root.bounding_box()
+    }
+}
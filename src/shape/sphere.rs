@@ -2,6 +2,9 @@ use cgmath::*;
 use shape::*;
 use color;
 
+// Minimum hit distance, keeps rays leaving the surface from hitting it again
+const EPSILON: f64 = 1e-6;
+
 #[derive(Serialize, Deserialize)]
 pub struct Sphere {
     material: Material,
@@ -17,6 +20,9 @@ impl Sphere {
             specular_color: color::WHITE,
             shininess: 15.0,
             reflectivity: 0.3,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
         };
         Sphere {
             center: center,
@@ -32,6 +38,10 @@ impl Sphere {
             material: m,
         }
     }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
 }
 
 impl Intersectable for Sphere {
@@ -48,9 +58,9 @@ impl Intersectable for Sphere {
             let delta_sq = delta.sqrt();
             let r0 = (-b - delta_sq) / (2.0 * a);
             let r1 = (-b + delta_sq) / (2.0 * a);
-            if r0 > 0.5 && r0 < r1 {
+            if r0 > EPSILON && r0 < r1 {
                 Some(r0)
-            } else if r1 > 0.5 {
+            } else if r1 > EPSILON {
                 Some(r1)
             } else {
                 None
@@ -71,25 +81,46 @@ impl Intersectable for Sphere {
             let delta_sq = delta.sqrt();
             let r0 = (-b - delta_sq) / (2.0 * a);
             let r1 = (-b + delta_sq) / (2.0 * a);
-            if r0 > 0.5 && r0 < r1 {
+            if r0 > EPSILON && r0 < r1 {
                 let q = p0 + d * r0;
                 let n = (q - self.center).normalize();
-                Some(Intersection {
-                    material: &self.material,
-                    point: q,
-                    normal: n,
-                })
-            } else if r1 > 0.5 {
+                Some(Intersection::new(&self.material, q, n))
+            } else if r1 > EPSILON {
                 let q = p0 + d * r1;
                 let n = (q - self.center).normalize();
-                Some(Intersection {
-                    material: &self.material,
-                    point: q,
-                    normal: n,
-                })
+                Some(Intersection::new(&self.material, q, n))
             } else {
                 None
             }
         }
     }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        let p0_min_c = p0 - self.center;
+        let a = d.dot(d);
+        let b = 2.0 * d.dot(p0_min_c);
+        let c = p0_min_c.dot(p0_min_c) - (self.radius * self.radius);
+        let delta = (b * b) - (4.0 * a * c);
+
+        if delta < 0.0 {
+            return Vec::new();
+        }
+
+        let delta_sq = delta.sqrt();
+        let r0 = (-b - delta_sq) / (2.0 * a);
+        let r1 = (-b + delta_sq) / (2.0 * a);
+        vec![r0, r1]
+            .into_iter()
+            .map(|t| {
+                let q = p0 + d * t;
+                let n = (q - self.center).normalize();
+                (t, Intersection::new(&self.material, q, n))
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3d::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
 }
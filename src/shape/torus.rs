@@ -0,0 +1,192 @@
+use cgmath::*;
+use shape::*;
+use poly;
+use vec3d::orthonormal_basis;
+use color;
+
+const EPSILON: f64 = 1e-6;
+
+/// Torus around `axis` through `center`
+#[derive(Serialize, Deserialize)]
+pub struct Torus {
+    material: Material,
+    center: Vec3d,
+    axis: Vec3d,
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3d,
+        axis: Vec3d,
+        major_radius: f64,
+        minor_radius: f64,
+        c: Color,
+    ) -> Torus {
+        let material = Material {
+            diffuse_color: c,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.3,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Torus::from_material(center, axis, major_radius, minor_radius, material)
+    }
+
+    pub fn from_material(
+        center: Vec3d,
+        axis: Vec3d,
+        major_radius: f64,
+        minor_radius: f64,
+        m: Material,
+    ) -> Torus {
+        Torus {
+            material: m,
+            center: center,
+            axis: axis.normalize(),
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    // Orthonormal frame with the torus axis as z
+    fn frame(&self) -> (Vec3d, Vec3d, Vec3d) {
+        let w = self.axis.normalize();
+        let (u, v) = orthonormal_basis(w);
+        (u, v, w)
+    }
+
+    // Returns the hit distance and the local frame hit point
+    fn hit(&self, p0: Vec3d, d: Vec3d) -> Option<(f64, Vec3d)> {
+        self.line_hits(p0, d).into_iter().find(|&(t, _)| t > EPSILON)
+    }
+
+    // Every crossing of the line, sorted, with local frame hit points
+    fn line_hits(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Vec3d)> {
+        let (u, v, w) = self.frame();
+        let rel = p0 - self.center;
+        let d = Vec3d::new(d.dot(u), d.dot(v), d.dot(w));
+        let mut o = Vec3d::new(rel.dot(u), rel.dot(v), rel.dot(w));
+
+        // Start the ray at the bounding sphere, small origins keep the
+        // quartic's coefficients in a sane range
+        let bound = self.major_radius + self.minor_radius;
+        let dd = d.dot(d);
+        let od = o.dot(d);
+        let delta = od * od - dd * (o.dot(o) - bound * bound);
+        if delta < 0.0 {
+            return Vec::new();
+        }
+        let t_off = (-od - delta.sqrt()) / dd;
+        o += d * t_off;
+
+        let r2 = self.major_radius * self.major_radius;
+        let od = o.dot(d);
+        let k = o.dot(o) + r2 - self.minor_radius * self.minor_radius;
+
+        let roots = poly::solve_quartic(
+            dd * dd,
+            4.0 * dd * od,
+            2.0 * dd * k + 4.0 * od * od - 4.0 * r2 * (d.x * d.x + d.y * d.y),
+            4.0 * k * od - 8.0 * r2 * (o.x * d.x + o.y * d.y),
+            k * k - 4.0 * r2 * (o.x * o.x + o.y * o.y),
+        );
+
+        roots
+            .into_iter()
+            .map(|t| (t + t_off, o + d * t))
+            .collect()
+    }
+
+    fn normal(&self, local: Vec3d) -> Vec3d {
+        let (u, v, w) = self.frame();
+        let ring = Vec3d::new(local.x, local.y, 0.0);
+        // Direction from the closest point of the center ring
+        let n = if ring.magnitude2() > 0.0 {
+            local - ring.normalize() * self.major_radius
+        } else {
+            local
+        };
+        (u * n.x + v * n.y + w * n.z).normalize()
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        self.hit(p0, d).map(|(t, _)| t)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        self.hit(p0, d).map(|(t, local)| {
+            Intersection::new(&self.material, p0 + d * t, self.normal(local))
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        self.line_hits(p0, d)
+            .into_iter()
+            .map(|(t, local)| {
+                (t, Intersection::new(&self.material, p0 + d * t, self.normal(local)))
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let a = self.axis;
+        let r = self.minor_radius;
+        // Ring of radius R perpendicular to the axis, grown by r in every direction
+        let ring = Vec3d::new(
+            (1.0 - a.x * a.x).max(0.0).sqrt(),
+            (1.0 - a.y * a.y).max(0.0).sqrt(),
+            (1.0 - a.z * a.z).max(0.0).sqrt(),
+        ) * self.major_radius + Vec3d::new(r, r, r);
+        Some(Aabb::new(self.center - ring, self.center + ring))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn torus() -> Torus {
+        Torus::new(Vec3d::zero(), Vec3d::new(0.0, 0.0, 1.0), 2.0, 0.5, color::WHITE)
+    }
+
+    fn assert_close(a: Vec3d, b: Vec3d) {
+        assert!((a - b).magnitude() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn ray_across_the_ring_crosses_four_times() {
+        let t = torus();
+        for &d in &[Vec3d::new(1.0, 0.0, 0.0), Vec3d::new(0.0, 1.0, 0.0)] {
+            let ts: Vec<f64> = t.crossings(d * -5.0, d).iter().map(|c| c.0).collect();
+            assert_eq!(ts.len(), 4);
+            for (t, e) in ts.iter().zip(&[2.5, 3.5, 6.5, 7.5]) {
+                assert!((t - e).abs() < 1e-9, "{:?}", ts);
+            }
+            let hit = t.intersect(d * -5.0, d).unwrap();
+            assert_close(hit.normal, -d);
+        }
+    }
+
+    #[test]
+    fn ray_along_the_axis() {
+        let t = torus();
+        let down = Vec3d::new(0.0, 0.0, -1.0);
+        assert!(t.intersect_dist(Vec3d::new(0.0, 0.0, 5.0), down).is_none());
+
+        let hit = t.intersect(Vec3d::new(2.0, 0.0, 5.0), down).unwrap();
+        assert!((hit.point.z - 0.5).abs() < 1e-9);
+        assert_close(hit.normal, Vec3d::new(0.0, 0.0, 1.0));
+        assert!(t.intersect_dist(Vec3d::new(3.0, 0.0, 5.0), down).is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use shape::*;
+use transform::Transform;
+use scene::CraycrayError;
+
+/// The shape a `Transformed` places in the scene
+pub enum ShapeRef {
+    /// Shape data, shared between every clone of the `Arc`
+    Shape(Arc<Shape>),
+    /// Named scene prototype, linked once the scene is loaded
+    Prototype(String, Option<Arc<Shape>>),
+}
+
+// What the scene file holds for a `ShapeRef`
+#[derive(Deserialize)]
+enum ShapeRefFile {
+    Shape(Box<Shape>),
+    Prototype(String),
+}
+
+impl Serialize for ShapeRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            ShapeRef::Shape(ref s) => {
+                serializer.serialize_newtype_variant("ShapeRef", 0, "Shape", &**s)
+            }
+            ShapeRef::Prototype(ref name, _) => {
+                serializer.serialize_newtype_variant("ShapeRef", 1, "Prototype", name)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ShapeRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ShapeRef, D::Error> {
+        ShapeRefFile::deserialize(deserializer).map(|r| match r {
+            ShapeRefFile::Shape(s) => ShapeRef::Shape(Arc::new(*s)),
+            ShapeRefFile::Prototype(name) => ShapeRef::Prototype(name, None),
+        })
+    }
+}
+
+/// Named shapes of the scene file, stored behind `Arc`s so instances share them
+pub fn serialize_prototypes<S>(
+    prototypes: &HashMap<String, Arc<Shape>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let sorted: BTreeMap<&String, &Shape> = prototypes.iter().map(|(k, v)| (k, &**v)).collect();
+    sorted.serialize(serializer)
+}
+
+pub fn deserialize_prototypes<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, Arc<Shape>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, Shape>::deserialize(deserializer)
+        .map(|m| m.into_iter().map(|(k, v)| (k, Arc::new(v))).collect())
+}
+
+/// Shape placed in the world by an affine transform. Rays are taken to the
+/// shape's own space, so a sphere can become an ellipsoid
+#[derive(Serialize, Deserialize)]
+pub struct Transformed {
+    #[serde(default)]
+    name: Option<String>,
+    shape: ShapeRef,
+    #[serde(default)]
+    transform: Transform,
+}
+
+impl Transformed {
+    pub fn new(shape: Arc<Shape>, transform: Transform) -> Transformed {
+        Transformed {
+            name: None,
+            shape: ShapeRef::Shape(shape),
+            transform: transform,
+        }
+    }
+
+    /// Names the node so it can be found with `Scene::find_node`
+    pub fn named(mut self, name: &str) -> Transformed {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn shape(&self) -> Option<&Shape> {
+        match self.shape {
+            ShapeRef::Shape(ref s) |
+            ShapeRef::Prototype(_, Some(ref s)) => Some(&**s),
+            ShapeRef::Prototype(_, None) => None,
+        }
+    }
+
+    // The wrapped shape if this is its only user
+    fn shape_mut(&mut self) -> Option<&mut Shape> {
+        match self.shape {
+            ShapeRef::Shape(ref mut s) |
+            ShapeRef::Prototype(_, Some(ref mut s)) => Arc::get_mut(s),
+            ShapeRef::Prototype(_, None) => None,
+        }
+    }
+
+    /// Calls `f` on the materials of the wrapped shape, shared shapes are skipped
+    pub fn map_materials<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&mut Material),
+    {
+        if let Some(s) = self.shape_mut() {
+            s.map_materials(f);
+        }
+    }
+
+    pub fn build_bvh(&mut self) {
+        if let Some(s) = self.shape_mut() {
+            s.build_bvh();
+        }
+    }
+
+    /// Depth first search for a node by name, also inside shared shapes
+    pub fn find_node(&self, name: &str) -> Option<&Shape> {
+        self.shape().and_then(|s| s.find_node(name))
+    }
+
+    /// Sets the transform of a named node inside the wrapped shape, shared
+    /// shapes can't be changed
+    pub fn set_node_transform(&mut self, name: &str, transform: &Transform) -> bool {
+        self.shape_mut()
+            .map_or(false, |s| s.set_node_transform(name, transform))
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.shape().map_or(false, |s| s.is_animated())
+    }
+
+    /// Animates the wrapped shape, shared shapes stay where they are
+    pub fn animate(&mut self, time: f64) -> bool {
+        self.shape_mut().map_or(false, |s| s.animate(time))
+    }
+
+    pub fn load_resources(&mut self, base_dir: &Path) -> Result<(), CraycrayError> {
+        match self.shape_mut() {
+            Some(s) => s.load_resources(base_dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Links prototype references, returns the name of a missing prototype
+    pub fn resolve_prototypes(
+        &mut self,
+        prototypes: &HashMap<String, Arc<Shape>>,
+    ) -> Result<(), String> {
+        if let ShapeRef::Prototype(ref name, ref mut shape) = self.shape {
+            match prototypes.get(name) {
+                Some(p) => *shape = Some(p.clone()),
+                None => return Err(name.clone()),
+            }
+            return Ok(());
+        }
+        match self.shape_mut() {
+            Some(s) => s.resolve_prototypes(prototypes),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Moves an object space hit at world distance `t` back to world space
+pub fn hit_to_world<'a>(
+    transform: &Transform,
+    inter: Intersection<'a>,
+    p0: Vec3d,
+    d: Vec3d,
+    t: f64,
+) -> Intersection<'a> {
+    Intersection {
+        point: p0 + d * t,
+        normal: transform.normal(inter.normal).normalize(),
+        ..inter
+    }
+}
+
+impl Intersectable for Transformed {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        let (local_p0, local_d, scale) = self.transform.ray_to_local(p0, d);
+        self.shape()
+            .and_then(|s| s.intersect_dist(local_p0, local_d))
+            .map(|t| t * scale)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        let (local_p0, local_d, scale) = self.transform.ray_to_local(p0, d);
+        self.shape()
+            .and_then(|s| s.intersect(local_p0, local_d))
+            .map(|inter| {
+                let t = (inter.point - local_p0).dot(local_d) * scale;
+                hit_to_world(&self.transform, inter, p0, d, t)
+            })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        let (local_p0, local_d, scale) = self.transform.ray_to_local(p0, d);
+        match self.shape() {
+            Some(s) => s.crossings(local_p0, local_d)
+                .into_iter()
+                .map(|(t, inter)| {
+                    let t = t * scale;
+                    (t, hit_to_world(&self.transform, inter, p0, d, t))
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.shape()
+            .and_then(|s| s.bounding_box())
+            .map(|b| self.transform.bbox(b))
+    }
+}
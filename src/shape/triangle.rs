@@ -0,0 +1,119 @@
+use cgmath::*;
+use shape::*;
+use color;
+
+const EPSILON: f64 = 1e-6;
+
+#[derive(Serialize, Deserialize)]
+pub struct Triangle {
+    material: Material,
+    a: Vec3d,
+    b: Vec3d,
+    c: Vec3d,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3d, b: Vec3d, c: Vec3d, color: Color) -> Triangle {
+        let material = Material {
+            diffuse_color: color,
+            ambient_color: color::BLACK,
+            specular_color: color::WHITE,
+            shininess: 15.0,
+            reflectivity: 0.1,
+            transparency: 0.0,
+            ior: 1.0,
+            trap_color: None,
+        };
+        Triangle {
+            a: a,
+            b: b,
+            c: c,
+            material: material,
+        }
+    }
+
+    pub fn from_material(a: Vec3d, b: Vec3d, c: Vec3d, material: Material) -> Triangle {
+        Triangle {
+            a: a,
+            b: b,
+            c: c,
+            material: material,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+}
+
+/// Möller-Trumbore ray/triangle test, returns distance along `d`
+pub fn intersect_triangle(p0: Vec3d, d: Vec3d, a: Vec3d, b: Vec3d, c: Vec3d) -> Option<f64> {
+    line_triangle(p0, d, a, b, c).and_then(|t| if t > EPSILON { Some(t) } else { None })
+}
+
+/// Like `intersect_triangle` but for the whole line, negative distances included
+pub fn line_triangle(p0: Vec3d, d: Vec3d, a: Vec3d, b: Vec3d, c: Vec3d) -> Option<f64> {
+    line_triangle_uv(p0, d, a, b, c).map(|(t, _, _)| t)
+}
+
+/// Like `line_triangle`, also returns the barycentric weights of `b` and `c`
+pub fn line_triangle_uv(
+    p0: Vec3d,
+    d: Vec3d,
+    a: Vec3d,
+    b: Vec3d,
+    c: Vec3d,
+) -> Option<(f64, f64, f64)> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = d.cross(e2);
+    let det = e1.dot(p);
+
+    if det.abs() < EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = p0 - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(e1);
+    let v = d.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    Some((e2.dot(q) * inv_det, u, v))
+}
+
+/// Geometric normal of a counter-clockwise wound triangle
+pub fn triangle_normal(a: Vec3d, b: Vec3d, c: Vec3d) -> Vec3d {
+    (b - a).cross(c - a).normalize()
+}
+
+impl Intersectable for Triangle {
+    fn intersect_dist(&self, p0: Vec3d, d: Vec3d) -> Option<f64> {
+        intersect_triangle(p0, d, self.a, self.b, self.c)
+    }
+
+    fn intersect(&self, p0: Vec3d, d: Vec3d) -> Option<Intersection> {
+        intersect_triangle(p0, d, self.a, self.b, self.c).map(|t| {
+            Intersection::new(&self.material, p0 + d * t, triangle_normal(self.a, self.b, self.c))
+        })
+    }
+
+    fn crossings(&self, p0: Vec3d, d: Vec3d) -> Vec<(f64, Intersection)> {
+        let n = triangle_normal(self.a, self.b, self.c);
+        line_triangle(p0, d, self.a, self.b, self.c)
+            .map(|t| (t, Intersection::new(&self.material, p0 + d * t, n)))
+            .into_iter()
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points([self.a, self.b, self.c].iter().cloned()))
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::{HashMap, HashSet};
+use std::f64;
+use std::f64::consts::PI;
+use std::ops::{Add, Mul};
+
+use vec3d::Vec3d;
+use color::Color;
+use material::Material;
+use shape::mesh::Mesh;
+
+// Weights of the vertices one level up that make up a new vertex
+type Stencil = Vec<(usize, f64)>;
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// Edges of a control mesh and what touches them
+struct Topology {
+    edges: Vec<(usize, usize)>,
+    edge_index: HashMap<(usize, usize), usize>,
+    edge_faces: Vec<Vec<usize>>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    fn new(n_vertices: usize, faces: &[Vec<usize>]) -> Topology {
+        let mut topo = Topology {
+            edges: Vec::new(),
+            edge_index: HashMap::new(),
+            edge_faces: Vec::new(),
+            vertex_edges: vec![Vec::new(); n_vertices],
+            vertex_faces: vec![Vec::new(); n_vertices],
+        };
+
+        for (f, face) in faces.iter().enumerate() {
+            for (k, &a) in face.iter().enumerate() {
+                let b = face[(k + 1) % face.len()];
+                topo.vertex_faces[a].push(f);
+
+                let key = edge_key(a, b);
+                let next = topo.edges.len();
+                let e = *topo.edge_index.entry(key).or_insert(next);
+                if e == next {
+                    topo.edges.push(key);
+                    topo.edge_faces.push(Vec::new());
+                    topo.vertex_edges[a].push(e);
+                    topo.vertex_edges[b].push(e);
+                }
+                topo.edge_faces[e].push(f);
+            }
+        }
+        topo
+    }
+
+    fn edge(&self, a: usize, b: usize) -> usize {
+        self.edge_index[&edge_key(a, b)]
+    }
+
+    fn other(&self, e: usize, v: usize) -> usize {
+        let (a, b) = self.edges[e];
+        if a == v {
+            b
+        } else {
+            a
+        }
+    }
+}
+
+// Adds `other` scaled by `k` to the stencil
+fn add_scaled(stencil: &mut Stencil, other: &[(usize, f64)], k: f64) {
+    stencil.extend(other.iter().map(|&(i, w)| (i, w * k)));
+}
+
+// Mixes in `sharp` by the fraction `w` of the sharpness
+fn blend(smooth: &[(usize, f64)], sharp: &[(usize, f64)], w: f64) -> Stencil {
+    let mut stencil = Vec::with_capacity(smooth.len() + sharp.len());
+    add_scaled(&mut stencil, smooth, 1.0 - w);
+    add_scaled(&mut stencil, sharp, w);
+    stencil
+}
+
+// Midpoint for sharp edges, blended with `smooth` below a sharpness of one
+fn edge_stencil(topo: &Topology, sharp: &[f64], e: usize, smooth: Stencil) -> Stencil {
+    let (a, b) = topo.edges[e];
+    let midpoint = vec![(a, 0.5), (b, 0.5)];
+    if sharp[e] >= 1.0 {
+        midpoint
+    } else if sharp[e] > 0.0 {
+        blend(&smooth, &midpoint, sharp[e])
+    } else {
+        smooth
+    }
+}
+
+fn apply<T>(stencils: &[Stencil], values: &[T]) -> Vec<T>
+where
+    T: Copy + Add<Output = T> + Mul<f64, Output = T>,
+{
+    stencils
+        .iter()
+        .map(|s| {
+            let (i, w) = s[0];
+            s[1..].iter().fold(values[i] * w, |acc, &(i, w)| acc + values[i] * w)
+        })
+        .collect()
+}
+
+/// Splits polygons into triangles around their first vertex
+pub fn fan_triangles(faces: &[Vec<usize>]) -> Vec<[usize; 3]> {
+    faces
+        .iter()
+        .flat_map(|face| (1..face.len() - 1).map(move |i| [face[0], face[i], face[i + 1]]))
+        .collect()
+}
+
+/// First crease that doesn't join two neighbouring corners of a face, which
+/// `ControlMesh::add_crease` would have no edge for
+pub fn stray_crease<'a, I>(faces: I, creases: &[(usize, usize, f64)]) -> Option<(usize, usize)>
+where
+    I: IntoIterator<Item = &'a [usize]>,
+{
+    let edges: HashSet<(usize, usize)> = faces
+        .into_iter()
+        .flat_map(|face| {
+            (0..face.len()).map(move |i| edge_key(face[i], face[(i + 1) % face.len()]))
+        })
+        .collect();
+    creases
+        .iter()
+        .map(|&(a, b, _)| (a, b))
+        .find(|&(a, b)| !edges.contains(&edge_key(a, b)))
+}
+
+/// Polygon mesh that is refined before rendering. Triangle meshes get Loop
+/// subdivision, anything else Catmull-Clark, which leaves only quads. Mesh
+/// boundaries and crease edges stay sharp
+pub struct ControlMesh {
+    vertices: Vec<Vec3d>,
+    colors: Vec<Color>,
+    faces: Vec<Vec<usize>>,
+    // Sharpness of the crease edges by their ordered vertex pair
+    creases: HashMap<(usize, usize), f64>,
+}
+
+impl ControlMesh {
+    /// Faces are counter-clockwise polygons of at least 3 vertices
+    pub fn new(vertices: Vec<Vec3d>, faces: Vec<Vec<usize>>) -> ControlMesh {
+        ControlMesh {
+            vertices: vertices,
+            colors: Vec::new(),
+            faces: faces,
+            creases: HashMap::new(),
+        }
+    }
+
+    /// Sets a color for every vertex, refined like the positions
+    pub fn with_colors(mut self, colors: Vec<Color>) -> ControlMesh {
+        self.colors = colors;
+        self
+    }
+
+    /// Marks the edge between `a` and `b` as a crease. It stays sharp for
+    /// `sharpness` levels, a fractional rest blends the sharp and smooth
+    /// rules in the level after that. Pairs that aren't edges are ignored
+    pub fn add_crease(&mut self, a: usize, b: usize, sharpness: f64) {
+        self.creases.insert(edge_key(a, b), sharpness);
+    }
+
+    /// Refines the mesh `levels` times
+    pub fn subdivide(self, levels: usize) -> ControlMesh {
+        (0..levels).fold(self, |mesh, _| mesh.subdivide_once())
+    }
+
+    /// Fan triangulates the faces into a mesh with generated normals
+    pub fn into_mesh(self, material: Material) -> Mesh {
+        let indices = fan_triangles(&self.faces);
+        Mesh::from_material(self.vertices, indices, material).with_colors(self.colors)
+    }
+
+    fn subdivide_once(&self) -> ControlMesh {
+        let topo = Topology::new(self.vertices.len(), &self.faces);
+        let sharp: Vec<f64> = (0..topo.edges.len())
+            .map(|e| {
+                // Boundaries and edges shared by more than two faces are always sharp
+                if topo.edge_faces[e].len() != 2 {
+                    f64::INFINITY
+                } else {
+                    self.creases.get(&topo.edges[e]).map_or(0.0, |&s| s.max(0.0))
+                }
+            })
+            .collect();
+
+        let (stencils, faces) = if self.faces.iter().all(|face| face.len() == 3) {
+            self.loop_level(&topo, &sharp)
+        } else {
+            self.catmull_clark_level(&topo, &sharp)
+        };
+
+        let colors = if self.colors.is_empty() {
+            Vec::new()
+        } else {
+            apply(&stencils, &self.colors)
+        };
+
+        ControlMesh {
+            vertices: apply(&stencils, &self.vertices),
+            colors: colors,
+            faces: faces,
+            creases: self.child_creases(&topo),
+        }
+    }
+
+    // Both halves of a crease are one level less sharp, edge points come
+    // right after the old vertices in both schemes
+    fn child_creases(&self, topo: &Topology) -> HashMap<(usize, usize), f64> {
+        let n = self.vertices.len();
+        let mut creases = HashMap::new();
+        for (&(a, b), &s) in self.creases.iter().filter(|&(_, &s)| s > 1.0) {
+            if let Some(&e) = topo.edge_index.get(&(a, b)) {
+                creases.insert(edge_key(a, n + e), s - 1.0);
+                creases.insert(edge_key(n + e, b), s - 1.0);
+            }
+        }
+        creases
+    }
+
+    // Stencil of a vertex with two sharp edges, which moves along the crease,
+    // or more, which is a corner. Blended with `smooth` by the average
+    // sharpness of the edges when that is below one
+    fn vertex_stencil<F>(&self, topo: &Topology, sharp: &[f64], v: usize, smooth: F) -> Stencil
+    where
+        F: Fn() -> Stencil,
+    {
+        let edges = &topo.vertex_edges[v];
+        if edges.is_empty() {
+            return vec![(v, 1.0)];
+        }
+        let sharp_edges: Vec<usize> = edges.iter().cloned().filter(|&e| sharp[e] > 0.0).collect();
+        let stencil = if sharp_edges.len() > 2 {
+            vec![(v, 1.0)]
+        } else if sharp_edges.len() == 2 {
+            vec![
+                (v, 0.75),
+                (topo.other(sharp_edges[0], v), 0.125),
+                (topo.other(sharp_edges[1], v), 0.125),
+            ]
+        } else {
+            return smooth();
+        };
+
+        let w = sharp_edges.iter().map(|&e| sharp[e]).sum::<f64>() / sharp_edges.len() as f64;
+        if w >= 1.0 {
+            stencil
+        } else {
+            blend(&smooth(), &stencil, w)
+        }
+    }
+
+    fn loop_level(&self, topo: &Topology, sharp: &[f64]) -> (Vec<Stencil>, Vec<Vec<usize>>) {
+        let n = self.vertices.len();
+        let mut stencils: Vec<Stencil> = (0..n)
+            .map(|v| {
+                self.vertex_stencil(topo, sharp, v, || {
+                    let valence = topo.vertex_edges[v].len() as f64;
+                    let c = 0.375 + 0.25 * (2.0 * PI / valence).cos();
+                    let beta = (0.625 - c * c) / valence;
+                    let mut s = vec![(v, 1.0 - valence * beta)];
+                    s.extend(topo.vertex_edges[v].iter().map(|&e| (topo.other(e, v), beta)));
+                    s
+                })
+            })
+            .collect();
+
+        for (e, &(a, b)) in topo.edges.iter().enumerate() {
+            let mut s = vec![(a, 0.375), (b, 0.375)];
+            for &f in &topo.edge_faces[e] {
+                let opposite = self.faces[f].iter().cloned().find(|&v| v != a && v != b);
+                s.push((opposite.unwrap_or(a), 0.125));
+            }
+            stencils.push(edge_stencil(topo, sharp, e, s));
+        }
+
+        let faces = self.faces
+            .iter()
+            .flat_map(|face| {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let ab = n + topo.edge(a, b);
+                let bc = n + topo.edge(b, c);
+                let ca = n + topo.edge(c, a);
+                vec![vec![a, ab, ca], vec![ab, b, bc], vec![ca, bc, c], vec![ab, bc, ca]]
+            })
+            .collect();
+        (stencils, faces)
+    }
+
+    fn catmull_clark_level(
+        &self,
+        topo: &Topology,
+        sharp: &[f64],
+    ) -> (Vec<Stencil>, Vec<Vec<usize>>) {
+        let n = self.vertices.len();
+        let face_points: Vec<Stencil> = self.faces
+            .iter()
+            .map(|face| {
+                let w = 1.0 / face.len() as f64;
+                face.iter().map(|&v| (v, w)).collect()
+            })
+            .collect();
+
+        // Average of the face points, twice the average of the edge midpoints
+        // and the vertex itself
+        let mut stencils: Vec<Stencil> = (0..n)
+            .map(|v| {
+                self.vertex_stencil(topo, sharp, v, || {
+                    let faces = &topo.vertex_faces[v];
+                    let edges = &topo.vertex_edges[v];
+                    let valence = edges.len() as f64;
+                    let mut s = vec![(v, (valence - 3.0) / valence)];
+                    for &f in faces {
+                        add_scaled(&mut s, &face_points[f], 1.0 / (faces.len() as f64 * valence));
+                    }
+                    for &e in edges {
+                        let mid = [(v, 0.5), (topo.other(e, v), 0.5)];
+                        add_scaled(&mut s, &mid, 2.0 / (valence * valence));
+                    }
+                    s
+                })
+            })
+            .collect();
+
+        for (e, &(a, b)) in topo.edges.iter().enumerate() {
+            let mut s = vec![(a, 0.25), (b, 0.25)];
+            for &f in &topo.edge_faces[e] {
+                add_scaled(&mut s, &face_points[f], 0.25);
+            }
+            stencils.push(edge_stencil(topo, sharp, e, s));
+        }
+        stencils.extend(face_points);
+
+        let ne = topo.edges.len();
+        let faces = self.faces
+            .iter()
+            .enumerate()
+            .flat_map(|(f, face)| {
+                let len = face.len();
+                (0..len)
+                    .map(|i| {
+                        let prev = face[(i + len - 1) % len];
+                        let next = face[(i + 1) % len];
+                        vec![
+                            face[i],
+                            n + topo.edge(face[i], next),
+                            n + ne + f,
+                            n + topo.edge(prev, face[i]),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        (stencils, faces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(sharpness: f64) -> ControlMesh {
+        let vertices = (0..8)
+            .map(|i| Vec3d::new((i & 4) as f64, (i & 2) as f64, (i & 1) as f64))
+            .collect();
+        let faces = vec![
+            vec![0, 1, 3, 2],
+            vec![4, 6, 7, 5],
+            vec![0, 4, 5, 1],
+            vec![2, 3, 7, 6],
+            vec![0, 2, 6, 4],
+            vec![1, 5, 7, 3],
+        ];
+        let mut mesh = ControlMesh::new(vertices, faces);
+        for &(a, b) in &[(0, 1), (1, 3), (3, 2), (2, 0)] {
+            mesh.add_crease(a, b, sharpness);
+        }
+        mesh
+    }
+
+    // Every vertex of `half` is halfway between the ones of `a` and `b`
+    fn assert_halfway(a: &[Vec3d], b: &[Vec3d], half: &[Vec3d]) {
+        assert_eq!(a.len(), half.len());
+        assert_eq!(b.len(), half.len());
+        for ((a, b), h) in a.iter().zip(b).zip(half) {
+            let d = (*a + *b) * 0.5 - *h;
+            assert!(d.x.abs() + d.y.abs() + d.z.abs() < 1e-12, "{:?} {:?} {:?}", a, b, h);
+        }
+    }
+
+    #[test]
+    fn fractional_sharpness_blends_the_rules() {
+        let smooth = cube(0.0).subdivide(1).vertices;
+        let sharp = cube(1.0).subdivide(1).vertices;
+        assert!(smooth != sharp);
+        assert_halfway(&smooth, &sharp, &cube(0.5).subdivide(1).vertices);
+    }
+
+    #[test]
+    fn sharpness_runs_out_by_levels() {
+        let one = cube(1.0).subdivide(2).vertices;
+        let two = cube(2.0).subdivide(2).vertices;
+        assert!(one != two);
+        assert_halfway(&one, &two, &cube(1.5).subdivide(2).vertices);
+    }
+
+    #[test]
+    fn stray_creases() {
+        let faces = [vec![0, 1, 2, 3], vec![3, 2, 4]];
+        let faces = || faces.iter().map(|face| &face[..]);
+        assert_eq!(stray_crease(faces(), &[(1, 0, 1.0), (2, 3, 1.0), (4, 3, 2.0)]), None);
+        assert_eq!(stray_crease(faces(), &[(0, 1, 1.0), (0, 2, 1.0)]), Some((0, 2)));
+        assert_eq!(stray_crease(faces(), &[(4, 9, 1.0)]), Some((4, 9)));
+    }
+}
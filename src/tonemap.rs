@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+use color::Color;
+
+/// Operator that compresses scene values into the displayable range
+#[derive(Copy, Clone, Debug)]
+pub enum ToneMap {
+    /// Cut every channel at 1.0
+    Clamp,
+    /// L / (1 + L) on the luminance, keeps the hue
+    Reinhard,
+    /// Reinhard with the given white point mapped to 1.0
+    ReinhardExtended(f64),
+    /// Narkowicz's fit of the ACES filmic curve, per channel
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    /// Parses `clamp`, `reinhard`, `reinhard-extended[:white]` or `aces`
+    fn from_str(s: &str) -> Result<ToneMap, String> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let arg = parts.next();
+
+        match (name, arg) {
+            ("clamp", None) => Ok(ToneMap::Clamp),
+            ("reinhard", None) => Ok(ToneMap::Reinhard),
+            ("reinhard-extended", None) => Ok(ToneMap::ReinhardExtended(4.0)),
+            ("reinhard-extended", Some(w)) => w.parse()
+                .map(ToneMap::ReinhardExtended)
+                .map_err(|_| format!("invalid white point: {}", w)),
+            ("aces", None) => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone mapping operator: {}", s)),
+        }
+    }
+}
+
+/// Exposure and tone mapping applied before quantizing to 8 bits
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMapper {
+    operator: ToneMap,
+    scale: f64,
+}
+
+impl Default for ToneMapper {
+    fn default() -> ToneMapper {
+        ToneMapper::new(ToneMap::Clamp, 0.0)
+    }
+}
+
+impl ToneMapper {
+    /// `exposure` is in stops, every +1 doubles the brightness
+    pub fn new(operator: ToneMap, exposure: f64) -> ToneMapper {
+        ToneMapper {
+            operator: operator,
+            scale: exposure.exp2(),
+        }
+    }
+
+    /// Maps a color into the [0, 1] range
+    pub fn map(&self, c: Color) -> Color {
+        let c = c * self.scale;
+        let mapped = match self.operator {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMap::ReinhardExtended(white) => {
+                scale_luminance(c, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMap::Aces => Color(aces(c.0), aces(c.1), aces(c.2)),
+        };
+        mapped.clamp()
+    }
+
+    /// Maps and quantizes a color
+    pub fn to_rgb(&self, c: Color) -> (u8, u8, u8) {
+        self.map(c).into()
+    }
+}
+
+// Rescales the color so its luminance becomes f(luminance)
+fn scale_luminance<F>(c: Color, f: F) -> Color
+where
+    F: Fn(f64) -> f64,
+{
+    let l = c.luminance();
+    if l > 0.0 {
+        c * (f(l) / l)
+    } else {
+        c
+    }
+}
+
+fn aces(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
@@ -0,0 +1,133 @@
+use cgmath::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+
+use aabb::Aabb;
+use vec3d::Vec3d;
+
+/// Single step of a transform as written in the scene file
+#[derive(Clone, Serialize, Deserialize)]
+pub enum TransformOp {
+    Translate(Vec3d),
+    Scale(Vec3d),
+    /// Rotation around `axis`, angle in radians
+    Rotate { axis: Vec3d, angle: f64 },
+    /// Row-major 4×4 matrix
+    Matrix([[f64; 4]; 4]),
+}
+
+impl TransformOp {
+    fn matrix(&self) -> Matrix4<f64> {
+        match *self {
+            TransformOp::Translate(v) => Matrix4::from_translation(v),
+            TransformOp::Scale(s) => Matrix4::from_nonuniform_scale(s.x, s.y, s.z),
+            TransformOp::Rotate { axis, angle } => {
+                Matrix4::from_axis_angle(axis.normalize(), Rad(angle))
+            }
+            TransformOp::Matrix(rows) => Matrix4::from(rows).transpose(),
+        }
+    }
+}
+
+/// Affine transform from object to world space, keeps its inverse around.
+/// Serializes as the list of steps it was built from
+#[derive(Clone)]
+pub struct Transform {
+    ops: Vec<TransformOp>,
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            ops: Vec::new(),
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    /// Applies `ops` in order, None if the result can't be inverted
+    pub fn new(ops: Vec<TransformOp>) -> Option<Transform> {
+        let matrix = ops.iter().fold(Matrix4::identity(), |m, op| op.matrix() * m);
+        matrix.invert().map(|inverse| {
+            Transform {
+                ops: ops,
+                matrix: matrix,
+                inverse: inverse,
+            }
+        })
+    }
+
+    pub fn matrix(&self) -> Matrix4<f64> {
+        self.matrix
+    }
+
+    /// Object to world
+    pub fn point(&self, p: Vec3d) -> Vec3d {
+        (self.matrix * p.extend(1.0)).truncate()
+    }
+
+    pub fn vector(&self, v: Vec3d) -> Vec3d {
+        (self.matrix * v.extend(0.0)).truncate()
+    }
+
+    /// Object space normal to world space, not normalized
+    pub fn normal(&self, n: Vec3d) -> Vec3d {
+        // Multiplies by the inverse transpose
+        let inv = &self.inverse;
+        Vec3d::new(
+            inv.x.truncate().dot(n),
+            inv.y.truncate().dot(n),
+            inv.z.truncate().dot(n),
+        )
+    }
+
+    /// World to object
+    pub fn inverse_point(&self, p: Vec3d) -> Vec3d {
+        (self.inverse * p.extend(1.0)).truncate()
+    }
+
+    pub fn inverse_vector(&self, v: Vec3d) -> Vec3d {
+        (self.inverse * v.extend(0.0)).truncate()
+    }
+
+    /// Takes a world space ray to object space. The direction is normalized,
+    /// the returned factor turns object space distances into world space ones
+    pub fn ray_to_local(&self, p0: Vec3d, d: Vec3d) -> (Vec3d, Vec3d, f64) {
+        let local_d = self.inverse_vector(d);
+        let len = local_d.magnitude();
+        (self.inverse_point(p0), local_d / len, 1.0 / len)
+    }
+
+    /// World space box around the transformed object space box
+    pub fn bbox(&self, b: Aabb) -> Aabb {
+        let corners = (0..8).map(|i| {
+            Vec3d::new(
+                if i & 1 == 0 { b.min.x } else { b.max.x },
+                if i & 2 == 0 { b.min.y } else { b.max.y },
+                if i & 4 == 0 { b.min.z } else { b.max.z },
+            )
+        });
+        Aabb::from_points(corners.map(|c| self.point(c)))
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+impl Serialize for Transform {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.ops.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Transform, D::Error> {
+        let ops = Vec::<TransformOp>::deserialize(deserializer)?;
+        Transform::new(ops).ok_or_else(|| D::Error::custom("transform is not invertible"))
+    }
+}
@@ -1,7 +1,19 @@
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3};
 
 pub type Vec3d = Vector3<f64>;
 
+/// Two unit vectors that form a right handed orthonormal frame with the unit vector `w`
+pub fn orthonormal_basis(w: Vec3d) -> (Vec3d, Vec3d) {
+    let helper = if w.x.abs() < 0.9 {
+        Vec3d::unit_x()
+    } else {
+        Vec3d::unit_y()
+    };
+    let u = helper.cross(w).normalize();
+    let v = w.cross(u);
+    (u, v)
+}
+
 pub trait Rotatable {
     fn rot_x(self, angle: f64) -> Vec3d;
     fn rot_y(self, angle: f64) -> Vec3d;
@@ -0,0 +1,138 @@
+use std::time::Instant;
+
+use sdl2;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::EventPump;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+use clap::ArgMatches;
+
+use craycray::scene::Scene;
+
+use {configure, draw, parse_size, tone_mapper};
+
+struct FpsCounter {
+    ts: Instant,
+    cnt: usize,
+    freq: usize,
+}
+
+impl FpsCounter {
+    pub fn new(freq: usize) -> Self {
+        let cnt = 0;
+        let ts = Instant::now();
+        FpsCounter { ts, cnt, freq }
+    }
+
+    pub fn update(&mut self) {
+        if self.cnt >= self.freq {
+            let elapsed = self.ts.elapsed();
+            let msecs = (u64::from(elapsed.as_secs()) * 1000) +
+                (u64::from(elapsed.subsec_nanos()) / 1_000_000);
+            self.ts = Instant::now();
+            eprintln!("FPS {}", (self.cnt as f32) / (msecs as f32 / 1000.0));
+            self.cnt = 0;
+        }
+
+        self.cnt += 1;
+    }
+}
+
+// Return true for quit
+fn handle_events(scene: &mut Scene, event_pump: &mut EventPump) -> bool {
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } |
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => return true,
+            Event::KeyDown {
+                keycode: Some(Keycode::W),
+                ..
+            } => {
+                scene.mv_camera_fwd();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::S),
+                ..
+            } => {
+                scene.mv_camera_back();
+            }
+            _ => {}
+        }
+    }
+
+    let rel_mouse = event_pump.relative_mouse_state();
+
+    let x_rot = f64::from(rel_mouse.x()) * (0.010);
+    let y_rot = f64::from(rel_mouse.y()) as f64 * (0.010);
+    scene.rot_camera(x_rot, y_rot);
+    false
+}
+
+// A simple test code that uses SDL for rendering
+pub fn view(matches: &ArgMatches) {
+    let fullscreen = matches.is_present("FULLSCREEN");
+    let width = parse_size(matches, "RESOLUTION", 512);
+    let mut scene = Scene::from_file("scene.json").unwrap();
+    configure(&mut scene, matches);
+    let tone_mapper = tone_mapper(matches);
+
+    // Stuff from sdl2-rust example
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+
+    // Fullscreen renders at the display's aspect ratio and scales up
+    let (window, height) = if fullscreen {
+        let display_mode = video_subsystem.desktop_display_mode(0).unwrap();
+        let height = width * display_mode.h as usize / display_mode.w as usize;
+        let window = video_subsystem
+            .window("craycray", display_mode.w as u32, display_mode.h as u32)
+            .fullscreen_desktop()
+            .build()
+            .unwrap();
+        (window, height)
+    } else {
+        let height = parse_size(matches, "HEIGHT", width);
+        let window = video_subsystem
+            .window("craycray", width as u32, height as u32)
+            .position_centered()
+            .build()
+            .unwrap();
+        (window, height)
+    };
+
+    let mut canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .unwrap();
+    sdl_context.mouse().show_cursor(false);
+    sdl_context.mouse().set_relative_mouse_mode(true);
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut fps = FpsCounter::new(10);
+
+    loop {
+        let quit = handle_events(&mut scene, &mut event_pump);
+        if quit {
+            break;
+        }
+
+        scene.step();
+
+        texture
+            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                draw(&scene, &tone_mapper, width, height, buffer, pitch);
+            })
+            .unwrap();
+        canvas.clear();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        fps.update();
+    }
+}